@@ -0,0 +1,173 @@
+extern crate x11;
+
+use x11::xlib;
+use super::super::libx;
+use super::layout::Rectangle;
+
+/// everything needed to draw the titlebar and border of one client
+#[derive(PartialEq, Clone)]
+pub struct Decoration {
+    pub window: xlib::Window,
+    pub titlebar: Option<Rectangle>,
+    pub title: Option<String>,
+    pub rec: Rectangle,
+    pub border: u32,
+    pub focused: bool,
+}
+
+/// off-screen pixmap holding the decorations a container draws for
+/// its clients. The pixmap is only re-rendered when one of the
+/// decorations or the size changes, and is copied to the window in
+/// one request, so focus changes and exposes don't flicker.
+pub struct Canvas {
+    context: libx::Context,
+    pixmap: xlib::Pixmap,
+    width: u32,
+    height: u32,
+    items: Vec<Decoration>,
+    dirty: bool,
+}
+
+impl Canvas {
+    pub fn new(context: libx::Context, window: xlib::Window, width: u32, height: u32) -> Canvas {
+        let width = if width > 0 { width } else { 1 };
+        let height = if height > 0 { height } else { 1 };
+        Canvas {
+            context: context,
+            pixmap: libx::create_pixmap(context, window, width, height),
+            width: width,
+            height: height,
+            items: Vec::new(),
+            dirty: true,
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width == 0 || height == 0 {
+            return
+        }
+        if width == self.width && height == self.height {
+            return
+        }
+        let pixmap = libx::create_pixmap(self.context, self.context.root, width, height);
+        libx::free_pixmap(self.context, self.pixmap);
+        self.pixmap = pixmap;
+        self.width = width;
+        self.height = height;
+        self.dirty = true;
+    }
+
+    /// replace the decoration of item.window, marking the canvas
+    /// dirty only if something visible changed
+    pub fn update(&mut self, item: Decoration) {
+        match self.items.iter().position(|d| d.window == item.window) {
+            Some(i) => {
+                if self.items[i] != item {
+                    self.items[i] = item;
+                    self.dirty = true;
+                }
+            }
+            None => {
+                self.items.push(item);
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// drop decorations of windows that are no longer clients
+    pub fn retain(&mut self, windows: &[xlib::Window]) {
+        let size = self.items.len();
+        self.items.retain(|d| windows.contains(&d.window));
+        if self.items.len() != size {
+            self.dirty = true;
+        }
+    }
+
+    pub fn render(&mut self, window: xlib::Window) {
+        if self.dirty {
+            self.paint();
+            self.dirty = false;
+        }
+        let mut context = self.context;
+        context.gc = context.background_gc;
+        libx::copy_area(context, self.pixmap, window,
+                        0, 0, self.width, self.height);
+    }
+
+    fn paint(&self) {
+        let mut context = self.context;
+        context.gc = context.background_gc;
+        libx::fill_rectangle(context, self.pixmap,
+                             0, 0, self.width, self.height);
+        for item in self.items.iter() {
+            if let Some(rec) = item.titlebar {
+                self.set_titlebar(item, rec);
+                self.set_title(item, rec);
+            }
+            self.set_border(item);
+        }
+    }
+
+    fn set_titlebar(&self, item: &Decoration, rec: Rectangle) {
+        let mut context = self.context;
+
+        context.gc = if item.focused {
+            context.focus_gc
+        }
+        else {
+            context.unfocus_gc
+        };
+
+        libx::fill_rectangle(context, self.pixmap,
+                             rec.x, rec.y,
+                             rec.width, rec.height);
+    }
+
+    fn set_title(&self, item: &Decoration, rec: Rectangle) {
+        let mut context = self.context;
+
+        context.gc = if item.focused {
+            context.focus_font_gc
+        }
+        else {
+            context.unfocus_font_gc
+        };
+
+        if let Some(ref s) = item.title {
+            let (_, dummy) = libx::text_extents(context, s.clone());
+
+            let x = rec.x - dummy.x as i32;
+            let y = rec.y - dummy.y as i32;
+
+            libx::draw_string(context, s.clone(), self.pixmap, x, y);
+        }
+    }
+
+    fn set_border(&self, item: &Decoration) {
+        let mut context = self.context;
+        let border = item.border;
+
+        context.gc = if item.focused {
+            context.focus_gc
+        }
+        else{
+            context.unfocus_gc
+        };
+        unsafe {
+            xlib::XSetLineAttributes(context.display, context.gc, border, 0, 0, 0);
+        }
+
+        let rec = item.rec;
+        libx::draw_rectangle(context, self.pixmap,
+                             rec.x-border as i32,
+                             rec.y-border as i32,
+                             rec.width+border*2-1 as u32,
+                             rec.height+border*2-1 as u32);
+    }
+}
+
+impl Drop for Canvas {
+    fn drop(&mut self) {
+        libx::free_pixmap(self.context, self.pixmap);
+    }
+}
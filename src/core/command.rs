@@ -0,0 +1,508 @@
+use super::criteria::Criteria;
+use super::layout::{self, LayoutDirection, MoveDirection};
+use super::handler::Resize;
+
+/// a piece of a config line or command
+#[derive(PartialEq, Clone, Debug)]
+pub enum Token {
+    Word(String),
+    // a word with quoted or escaped parts, meant literally
+    Quoted(String),
+    // [...] including the brackets, for Criteria::parse
+    Criteria(String),
+    // ; starts a new command with its own criteria
+    Semicolon,
+    // , chains a command on the same criteria
+    Comma,
+}
+
+/// split s into tokens. Words are separated by any whitespace,
+/// "..." quotes a word and \ escapes the next character.
+pub fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    match tokenize_spans(s) {
+        Ok((tokens, _)) => { Ok(tokens) }
+        Err((_, err)) => { Err(err) }
+    }
+}
+
+/// tokenize, also giving the byte offset each token starts at, or
+/// the offset of an unterminated quote or bracket
+pub fn tokenize_spans(s: &str) -> Result<(Vec<Token>, Vec<usize>), (usize, String)> {
+    let mut tokens = Vec::new();
+    let mut offsets = Vec::new();
+    let mut word = String::new();
+    // offset of the current word, a quoted word may be empty
+    let mut start: Option<usize> = None;
+    let mut quoted = false;
+    let mut chars = s.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '[' if start.is_none() => {
+                let mut raw = String::from("[");
+                let mut in_quote = false;
+                loop {
+                    match chars.next() {
+                        Some((_, '\\')) => {
+                            raw.push('\\');
+                            if let Some((_, n)) = chars.next() {
+                                raw.push(n);
+                            }
+                        }
+                        Some((_, '"')) => {
+                            in_quote = !in_quote;
+                            raw.push('"');
+                        }
+                        Some((_, ']')) if !in_quote => {
+                            raw.push(']');
+                            break
+                        }
+                        Some((_, n)) => { raw.push(n); }
+                        None => { return Err((i, "unterminated [".to_string())) }
+                    }
+                }
+                tokens.push(Token::Criteria(raw));
+                offsets.push(i);
+            }
+            '"' => {
+                if start.is_none() {
+                    start = Some(i);
+                }
+                quoted = true;
+                loop {
+                    match chars.next() {
+                        Some((_, '\\')) => {
+                            if let Some((_, n)) = chars.next() {
+                                word.push(n);
+                            }
+                        }
+                        Some((_, '"')) => { break }
+                        Some((_, n)) => { word.push(n); }
+                        None => { return Err((i, "unterminated quote".to_string())) }
+                    }
+                }
+            }
+            '\\' => {
+                if start.is_none() {
+                    start = Some(i);
+                }
+                quoted = true;
+                if let Some((_, n)) = chars.next() {
+                    word.push(n);
+                }
+            }
+            ';' | ',' => {
+                if let Some(o) = start.take() {
+                    tokens.push(word_token(&word, quoted));
+                    offsets.push(o);
+                }
+                word.clear();
+                quoted = false;
+                tokens.push(if c == ';' { Token::Semicolon } else { Token::Comma });
+                offsets.push(i);
+            }
+            c if c.is_whitespace() => {
+                if let Some(o) = start.take() {
+                    tokens.push(word_token(&word, quoted));
+                    offsets.push(o);
+                }
+                word.clear();
+                quoted = false;
+            }
+            c => {
+                if start.is_none() {
+                    start = Some(i);
+                }
+                word.push(c);
+            }
+        }
+    }
+    if let Some(o) = start {
+        tokens.push(word_token(&word, quoted));
+        offsets.push(o);
+    }
+    Ok((tokens, offsets))
+}
+
+fn word_token(word: &str, quoted: bool) -> Token {
+    if quoted {
+        Token::Quoted(word.to_string())
+    }
+    else {
+        Token::Word(word.to_string())
+    }
+}
+
+/// the text of a word, quoted or not
+pub fn text(token: &Token) -> Option<&str> {
+    match *token {
+        Token::Word(ref w) | Token::Quoted(ref w) => { Some(w.as_str()) }
+        _ => { None }
+    }
+}
+
+pub enum WorkspaceTarget {
+    Next,
+    Prev,
+    Name(String),
+}
+
+pub enum OutputTarget {
+    Direction(MoveDirection),
+    Name(String),
+}
+
+pub enum FocusTarget {
+    Direction(MoveDirection),
+    Parent,
+    Child,
+    Last,
+    // cycle through recently focused windows, forward or not
+    Mru(bool),
+    // the window criteria point at
+    Target,
+    Output(OutputTarget),
+}
+
+pub enum Border {
+    None,
+    Normal,
+}
+
+pub enum Floating {
+    Enable,
+    Disable,
+    Toggle,
+}
+
+pub enum Command {
+    // program and arguments as words for /bin/sh, workspace for its first window
+    Exec(Vec<String>, Option<String>),
+    Layout(layout::Type),
+    Fullscreen,
+    Split,
+    Workspace(WorkspaceTarget),
+    MoveToWorkspace(String),
+    // rename workspace old, or the current one, to new
+    Rename(Option<String>, String),
+    MoveWorkspaceToOutput(OutputTarget),
+    Resize(LayoutDirection, Resize),
+    Focus(FocusTarget),
+    Move(MoveDirection),
+    Kill,
+    Floating(Floating),
+    Border(Border),
+    Mode(String),
+    Reload,
+}
+
+/// commands separated by commas, run on the windows matching criteria
+/// or on the focus
+pub struct Action {
+    pub criteria: Option<Criteria>,
+    pub commands: Vec<Command>,
+}
+
+/// parse `[criteria] cmd, cmd; cmd` into actions. Errors carry the
+/// index of the token the failing command starts at.
+pub fn parse(tokens: &[Token]) -> Result<Vec<Action>, (usize, String)> {
+    let mut actions = Vec::new();
+    let mut index = 0;
+    for group in tokens.split(|t| *t == Token::Semicolon) {
+        let group_start = index;
+        index += group.len() + 1;
+        if group.is_empty() {
+            continue
+        }
+        let (criteria, rest) = match group[0] {
+            Token::Criteria(ref raw) => {
+                match Criteria::parse(raw) {
+                    Some((c, _)) => { (Some(c), &group[1..]) }
+                    None => { return Err((group_start, format!("invalid criteria {}", raw))) }
+                }
+            }
+            _ => { (None, group) }
+        };
+
+        let mut commands = Vec::new();
+        let mut cmd_start = group_start + group.len() - rest.len();
+        for cmd in rest.split(|t| *t == Token::Comma) {
+            let mut words = Vec::new();
+            for (i, t) in cmd.iter().enumerate() {
+                match text(t) {
+                    Some(w) => { words.push(w); }
+                    None => { return Err((cmd_start + i, "criteria must come first".to_string())) }
+                }
+            }
+            // exec needs to know which words were quoted
+            let parsed = if words.get(0) == Some(&"exec") {
+                parse_exec(&cmd[1..]).map(|(args, workspace)| Command::Exec(args, workspace))
+            }
+            else {
+                parse_command(&words)
+            };
+            match parsed {
+                Ok(c) => { commands.push(c); }
+                Err(err) => { return Err((cmd_start, err)) }
+            }
+            cmd_start += cmd.len() + 1;
+        }
+        actions.push(Action {
+            criteria: criteria,
+            commands: commands,
+        });
+    }
+    if actions.is_empty() {
+        return Err((0, "missing command".to_string()))
+    }
+    Ok(actions)
+}
+
+fn workspace_key(arg: Option<&&str>) -> Result<String, String> {
+    match arg {
+        Some(name) if !name.is_empty() => { Ok(name.to_string()) }
+        _ => { Err("missing workspace".to_string()) }
+    }
+}
+
+// the name made of all words, like i3 does for unquoted names
+fn workspace_name(args: &[&str]) -> Result<String, String> {
+    let name = args.join(" ");
+    workspace_key(Some(&name.as_str()))
+}
+
+fn direction(arg: Option<&&str>) -> Option<MoveDirection> {
+    match arg {
+        Some(&"left") => Some(MoveDirection::Left),
+        Some(&"right") => Some(MoveDirection::Right),
+        Some(&"up") => Some(MoveDirection::Up),
+        Some(&"down") => Some(MoveDirection::Down),
+        _ => None
+    }
+}
+
+/// the arguments of exec: `[--workspace N] program args...`, giving
+/// the program and arguments as words for /bin/sh. Quoted words are
+/// quoted for the shell, unless a single one is the whole command.
+pub fn parse_exec(args: &[Token]) -> Result<(Vec<String>, Option<String>), String> {
+    let mut words = Vec::new();
+    for t in args.iter() {
+        match text(t) {
+            Some(w) => { words.push(w); }
+            None => { return Err("exec: quote ; , and [ in arguments".to_string()) }
+        }
+    }
+    let (workspace, start) = if words.get(0) == Some(&"--workspace") {
+        (Some(try!(workspace_key(words.get(1)))), 2)
+    }
+    else {
+        (None, 0)
+    };
+    if start >= args.len() {
+        return Err("exec: missing program".to_string())
+    }
+    let args = &args[start..];
+    if args.len() == 1 {
+        return Ok((vec![words[start].to_string()], workspace))
+    }
+    let args = args.iter().map(|t| {
+        match *t {
+            Token::Quoted(ref w) => { shell_quote(w) }
+            _ => { text(t).unwrap().to_string() }
+        }
+    }).collect();
+    Ok((args, workspace))
+}
+
+fn shell_quote(word: &str) -> String {
+    format!("'{}'", word.replace("'", "'\\''"))
+}
+
+fn output_target(arg: Option<&&str>) -> Result<OutputTarget, String> {
+    match direction(arg) {
+        Some(d) => { Ok(OutputTarget::Direction(d)) }
+        None => {
+            match arg {
+                Some(name) => { Ok(OutputTarget::Name(name.to_string())) }
+                None => { Err("missing output".to_string()) }
+            }
+        }
+    }
+}
+
+pub fn parse_command(words: &[&str]) -> Result<Command, String> {
+    if words.is_empty() {
+        return Err("missing command".to_string())
+    }
+    let (name, args) = words.split_at(1);
+    let cmd = match name[0] {
+        "exec" => {
+            let tokens: Vec<Token> = args.iter().map(|a| Token::Word(a.to_string())).collect();
+            let (args, workspace) = try!(parse_exec(&tokens));
+            Command::Exec(args, workspace)
+        }
+        "layout" => {
+            match args.get(0) {
+                Some(&"split") => Command::Layout(layout::Type::Tiling),
+                Some(&"tab") => Command::Layout(layout::Type::Tab),
+                _ => { return Err("layout: expected split or tab".to_string()) }
+            }
+        }
+        "fullscreen" => Command::Fullscreen,
+        "split" => Command::Split,
+        "workspace" => {
+            match (args.get(0), args.len()) {
+                (Some(&"next"), 1) => Command::Workspace(WorkspaceTarget::Next),
+                (Some(&"prev"), 1) => Command::Workspace(WorkspaceTarget::Prev),
+                _ => Command::Workspace(WorkspaceTarget::Name(try!(workspace_name(args)))),
+            }
+        }
+        "window" => Command::MoveToWorkspace(try!(workspace_name(args))),
+        "resize" => {
+            let resize = match args.get(0) {
+                Some(&"shrink") => Resize::Shrink,
+                Some(&"grow") => Resize::Grow,
+                _ => { return Err("resize: expected shrink or grow".to_string()) }
+            };
+            let direction = match args.get(1) {
+                Some(&"width") => LayoutDirection::Vertical,
+                Some(&"height") => LayoutDirection::Horizontal,
+                _ => { return Err("resize: expected width or height".to_string()) }
+            };
+            Command::Resize(direction, resize)
+        }
+        "focus" => {
+            let target = match args.get(0) {
+                None => FocusTarget::Target,
+                Some(&"parent") => FocusTarget::Parent,
+                Some(&"child") => FocusTarget::Child,
+                Some(&"last") => FocusTarget::Last,
+                Some(&"mru") => FocusTarget::Mru(args.get(1) != Some(&"prev")),
+                Some(&"output") => FocusTarget::Output(try!(output_target(args.get(1)))),
+                a => {
+                    match direction(a) {
+                        Some(d) => FocusTarget::Direction(d),
+                        None => { return Err("focus: unknown target".to_string()) }
+                    }
+                }
+            };
+            Command::Focus(target)
+        }
+        "move" => {
+            // move [to] workspace 3, move workspace to output left
+            let args = if args.get(0) == Some(&"to") { &args[1..] } else { args };
+            if args.len() >= 3 && args[..3] == ["workspace", "to", "output"] {
+                Command::MoveWorkspaceToOutput(try!(output_target(args.get(3))))
+            }
+            else if args.get(0) == Some(&"workspace") {
+                Command::MoveToWorkspace(try!(workspace_name(&args[1..])))
+            }
+            else {
+                match direction(args.get(0)) {
+                    Some(d) => Command::Move(d),
+                    None => { return Err("move: expected a direction or workspace".to_string()) }
+                }
+            }
+        }
+        "rename" => {
+            // rename workspace [old] to new, names split at the first to
+            if args.get(0) != Some(&"workspace") {
+                return Err("rename: expected workspace".to_string())
+            }
+            let to = match args.iter().position(|a| *a == "to") {
+                Some(i) => { i }
+                None => { return Err("rename: expected to".to_string()) }
+            };
+            let old = if to == 1 { None } else { Some(try!(workspace_name(&args[1..to]))) };
+            Command::Rename(old, try!(workspace_name(&args[to+1..])))
+        }
+        "kill" => Command::Kill,
+        "reload" => Command::Reload,
+        "floating" => {
+            match args.get(0) {
+                Some(&"enable") => Command::Floating(Floating::Enable),
+                Some(&"disable") => Command::Floating(Floating::Disable),
+                Some(&"toggle") => Command::Floating(Floating::Toggle),
+                _ => { return Err("floating: expected enable, disable or toggle".to_string()) }
+            }
+        }
+        "border" => {
+            match args.get(0) {
+                Some(&"none") => Command::Border(Border::None),
+                Some(&"normal") => Command::Border(Border::Normal),
+                _ => { return Err("border: expected none or normal".to_string()) }
+            }
+        }
+        "mode" => {
+            match args.get(0) {
+                Some(name) => Command::Mode(name.to_string()),
+                None => { return Err("mode: missing name".to_string()) }
+            }
+        }
+        n => { return Err(format!("unknown command {}", n)) }
+    };
+    Ok(cmd)
+}
+
+#[test]
+fn test_command_chain() {
+    let tokens = tokenize("[class=\"a b\"]\tkill,  focus ; exec notify-send \"x; y\" \\\"").unwrap();
+    assert_eq!(tokens[0], Token::Criteria("[class=\"a b\"]".to_string()));
+    assert_eq!(tokens[2], Token::Comma);
+    assert_eq!(tokens[6], Token::Word("notify-send".to_string()));
+    assert_eq!(tokens[7], Token::Quoted("x; y".to_string()));
+    assert_eq!(tokens[8], Token::Quoted("\"".to_string()));
+
+    let actions = parse(&tokens).unwrap();
+    assert_eq!(actions.len(), 2);
+    assert!(actions[0].criteria.is_some());
+    assert_eq!(actions[0].commands.len(), 2);
+    match actions[1].commands[0] {
+        Command::Exec(ref args, None) => { assert_eq!(args.len(), 3); }
+        _ => { panic!("expected exec") }
+    }
+    assert!(parse(&tokenize("kill,").unwrap()).is_err());
+
+    match parse_command(&["floating", "disable"]) {
+        Ok(Command::Floating(Floating::Disable)) => {}
+        _ => { panic!("expected floating disable") }
+    }
+}
+
+#[test]
+fn test_workspace_commands() {
+    match parse_command(&["rename", "workspace", "10", "to", "10: chat"]) {
+        Ok(Command::Rename(Some(ref old), ref new)) => {
+            assert_eq!(old, "10");
+            assert_eq!(new, "10: chat");
+        }
+        _ => { panic!("expected rename") }
+    }
+    // unquoted names take all the words, like in i3
+    match parse_command(&["rename", "workspace", "1", "to", "1:", "term"]) {
+        Ok(Command::Rename(Some(ref old), ref new)) => {
+            assert_eq!(old, "1");
+            assert_eq!(new, "1: term");
+        }
+        _ => { panic!("expected rename") }
+    }
+    match parse_command(&["rename", "workspace", "to", "my", "mail"]) {
+        Ok(Command::Rename(None, ref new)) => { assert_eq!(new, "my mail"); }
+        _ => { panic!("expected rename") }
+    }
+    match parse_command(&["workspace", "3:", "web"]) {
+        Ok(Command::Workspace(WorkspaceTarget::Name(ref key))) => { assert_eq!(key, "3: web"); }
+        _ => { panic!("expected workspace") }
+    }
+    match parse_command(&["workspace", "next", "door"]) {
+        Ok(Command::Workspace(WorkspaceTarget::Name(ref key))) => { assert_eq!(key, "next door"); }
+        _ => { panic!("expected workspace") }
+    }
+    match parse_command(&["move", "to", "workspace", "3:", "web"]) {
+        Ok(Command::MoveToWorkspace(ref key)) => { assert_eq!(key, "3: web"); }
+        _ => { panic!("expected move to workspace") }
+    }
+    assert!(parse_command(&["rename", "workspace", "1", "to"]).is_err());
+    assert!(parse_command(&["rename", "workspace", "1"]).is_err());
+    assert!(parse_command(&["window"]).is_err());
+}
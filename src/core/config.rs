@@ -1,31 +1,108 @@
 extern crate x11;
+extern crate glob;
+extern crate libc;
 
 use std::collections::HashMap;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::io::Lines;
 use std::fs::File;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::fmt;
+use std::mem;
+use std::process;
 use std::ffi;
 use std::env;
+use std::os::unix::process::CommandExt;
 use x11::xlib;
 use std::boxed::Box;
 
 use super::layout::{self, LayoutDirection, MoveDirection};
-use super::handler::{self, KeyBind};
+use super::handler::{self, KeyBind, MouseBind, Binding};
+use super::criteria::{Criteria, Rule, Assign};
+use super::command::{self, Token, Command, WorkspaceTarget, FocusTarget, Border, Floating};
 
-pub fn build_cmd(tokens: &[&str]) -> Command {
-    let (name, args) = tokens.split_at(1);
-    let mut cmd = Command::new(name[0]);
-    cmd.args(args);
+/// a command running words, as parse_exec gives them, with /bin/sh in a
+/// new session, so pipes, redirections and $PATH lookup work and the
+/// program outlives us
+pub fn build_cmd(words: &[&str]) -> process::Command {
+    let mut cmd = process::Command::new("/bin/sh");
+    cmd.arg("-c").arg(words.join(" "));
+    unsafe {
+        cmd.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
     cmd
 }
 
+pub const DEFAULT_MODE: &'static str = "default";
+
+fn word(token: Option<&Token>) -> Option<&str> {
+    token.and_then(|t| command::text(t))
+}
+
+/// a program started by an exec or exec_always line
+pub struct Exec {
+    pub args: Vec<String>,
+    // workspace the first window of the program opens on
+    pub workspace: Option<String>,
+    // exec_always also runs when the config is reloaded
+    pub always: bool,
+}
+
+/// a problem in a config file. Line and column count from 1, problems
+/// with the whole file, like a missing one, have no position.
+pub struct ConfigError {
+    pub file: String,
+    // line and column
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.position {
+            Some((line, column)) => {
+                write!(f, "{}:{}:{}: {}", self.file, line, column, self.message)
+            }
+            None => {
+                write!(f, "{}: {}", self.file, self.message)
+            }
+        }
+    }
+}
+
 pub struct Config {
     mod_key: u32,
-    pub bindsyms: HashMap<KeyBind, handler::HandleFn>,
+    pub bindsyms: HashMap<KeyBind, Binding>,
+    pub modes: HashMap<String, HashMap<KeyBind, Binding>>,
+    pub mousebinds: HashMap<MouseBind, handler::HandleFn>,
+    pub rules: Vec<Rule>,
+    pub assigns: Vec<Assign>,
     pub titlebar_height: u32,
+    // milliseconds to wait for the next key of a chord
+    pub chord_timeout: u64,
+    pub focus_wrapping: bool,
+    // workspace N output NAME...: outputs a workspace lives on
+    pub output_assigns: Vec<(String, Vec<String>)>,
+    // exec lines, run by the window manager once it is initialised
+    pub execs: Vec<Exec>,
+    // variables from set, and whether their value was quoted
+    vars: Vec<(String, String, bool)>,
+    // name of the mode block being parsed
+    block: Option<String>,
+    // file and line being parsed, files whose includes are being
+    // parsed, and the errors found so far
+    file: String,
+    line: usize,
+    including: Vec<PathBuf>,
+    errors: Vec<ConfigError>,
+    // mode commands found so far, checked once all modes are known
+    mode_targets: Vec<(String, ConfigError)>,
+    // modes switched to by the line being parsed, with their token index
+    line_modes: Vec<(String, usize)>,
 }
 
 impl Config {
@@ -33,184 +110,637 @@ impl Config {
         Config {
             mod_key: xlib::Mod4Mask,
             bindsyms: HashMap::new(),
+            modes: HashMap::new(),
+            mousebinds: HashMap::new(),
+            rules: Vec::new(),
+            assigns: Vec::new(),
             titlebar_height: 16,
+            chord_timeout: 1000,
+            focus_wrapping: true,
+            output_assigns: Vec::new(),
+            execs: Vec::new(),
+            vars: Vec::new(),
+            block: None,
+            file: String::new(),
+            line: 0,
+            including: Vec::new(),
+            errors: Vec::new(),
+            mode_targets: Vec::new(),
+            line_modes: Vec::new(),
         }
     }
 
-    pub fn load(&mut self) {
-        let mut pathbuf = match env::var_os("HOME") {
-            Some(v) => {
-                PathBuf::from(v)
+    pub fn get_binding(&mut self, mode: &str, keys: &[KeyBind]) -> Option<&mut Binding> {
+        if mode == DEFAULT_MODE {
+            Binding::lookup(&mut self.bindsyms, keys)
+        }
+        else {
+            match self.modes.get_mut(mode) {
+                Some(binds) => { Binding::lookup(binds, keys) }
+                None => { None }
             }
+        }
+    }
+
+    /// the config file to use when none is given: the first that exists of
+    /// $XDG_CONFIG_HOME/rustile/config, ~/.rustile and /etc/xdg/rustile/config
+    pub fn find_path() -> Option<PathBuf> {
+        let home = env::var_os("HOME").map(|h| PathBuf::from(h));
+        let mut paths = Vec::new();
+        match env::var_os("XDG_CONFIG_HOME") {
+            Some(v) => { paths.push(PathBuf::from(v).join("rustile/config")); }
             None => {
-                // can't find HOME, do nothing
-                return;
+                if let Some(ref h) = home {
+                    paths.push(h.join(".config/rustile/config"));
+                }
             }
-        };
+        }
+        if let Some(ref h) = home {
+            paths.push(h.join(".rustile"));
+        }
+        paths.push(PathBuf::from("/etc/xdg/rustile/config"));
+        paths.into_iter().find(|p| p.is_file())
+    }
 
-        pathbuf.push(".rustile");
-        match File::open(pathbuf.as_path()) {
-            Ok(f) => {
-                let buf = BufReader::new(f);
-                for line in buf.lines() {
-                    match line {
-                        Ok(s) => {
-                            self.parse_line(s);
-                        }
-                        Err(err) => {
-                            // do nothing
-                        }
+    /// read the config at path, or the one find_path finds, returning
+    /// the problems found in it
+    pub fn load(&mut self, path: Option<&Path>) -> Vec<ConfigError> {
+        let path = match path {
+            Some(p) => { p.to_path_buf() }
+            None => {
+                match Config::find_path() {
+                    Some(p) => { p }
+                    None => {
+                        // use default config
+                        println!("no config file");
+                        return Vec::new();
                     }
                 }
             }
-            Err(err) => {
-                // use default config
-                println!("no config file");
-            }
+        };
+        self.load_file(path.as_path())
+    }
+
+    /// parse the file at path, lines with errors are skipped
+    pub fn load_file(&mut self, path: &Path) -> Vec<ConfigError> {
+        if let Err(err) = self.read_file(path) {
+            self.file_error(path, err);
+        }
+        if let Some(name) = self.block.take() {
+            self.file_error(path, format!("mode \"{}\" is not closed", name));
         }
+        self.check_modes();
+        mem::replace(&mut self.errors, Vec::new())
     }
 
-    fn parse_line(&mut self, line: String) {
-        // # is comment
-        if !line.starts_with("#") {
-            let tokens: Vec<&str> = line.split(' ').collect();
-            let (cmd, args) = tokens.split_at(1);
-            match cmd[0] {
-                "set" => {
-                    if args.len() > 1 {
-                        self.set_var(args[0], args[1]);
-                    }
-                }
-                "exec" => {
-                    let mut cmd = build_cmd(&tokens);
-                    cmd.spawn();
-                }
-                "bind" => {
-                    self.bind_sym(args);
+    // parse the lines of a file, also for includes. Errors are about
+    // the file as a whole, those of its lines are collected.
+    fn read_file(&mut self, path: &Path) -> Result<(), String> {
+        if self.including.iter().any(|p| p == path) {
+            return Err(format!("{} includes itself", path.display()))
+        }
+        let f = match File::open(path) {
+            Ok(f) => { f }
+            Err(err) => { return Err(err.to_string()) }
+        };
+
+        let parent = (self.file.clone(), self.line);
+        self.file = path.display().to_string();
+        self.line = 0;
+        self.including.push(path.to_path_buf());
+        let buf = BufReader::new(f);
+        for line in buf.lines() {
+            self.line += 1;
+            match line {
+                Ok(s) => {
+                    self.parse_line(s);
                 }
-                _ => {
-                    // not supported cmd, ignore
+                Err(err) => {
+                    self.error(1, err.to_string());
                 }
             }
         }
+        self.including.pop();
+        self.file = parent.0;
+        self.line = parent.1;
+        Ok(())
     }
 
-    fn bind_sym(&mut self, args: &[&str]) {
-        let (keyseq, cmd) = args.split_at(1);
-        let keys: Vec<&str> = keyseq[0].split("+").collect();
+    // include <glob>, relative to the including file
+    fn include(&mut self, pattern: &str) -> Result<(), String> {
+        let mut path = PathBuf::new();
+        if pattern.starts_with("~/") {
+            if let Some(home) = env::var_os("HOME") {
+                path.push(home);
+            }
+            path.push(&pattern[2..]);
+        }
+        else {
+            if let Some(dir) = Path::new(&self.file).parent() {
+                path.push(dir);
+            }
+            path.push(pattern);
+        }
+        let paths = match glob::glob(&path.to_string_lossy()) {
+            Ok(paths) => { paths }
+            Err(err) => { return Err(format!("include: {}", err)) }
+        };
+        let mut paths: Vec<PathBuf> = paths.filter_map(|p| p.ok()).collect();
+        paths.sort();
+        for p in paths.iter() {
+            if let Err(err) = self.read_file(p) {
+                return Err(format!("include: {}: {}", p.display(), err))
+            }
+        }
+        Ok(())
+    }
 
-        let bind = KeyBind::build(self.mod_key, &keys);
+    /// replace the variables set so far in s. At each $ the longest
+    /// name set that isn't followed by more letters, digits or _ is taken.
+    fn substitute(&self, s: &str) -> String {
+        self.substitute_quoted(s).0
+    }
+
+    // substitute, also telling whether a quoted value was put in
+    fn substitute_quoted(&self, s: &str) -> (String, bool) {
+        let mut vars: Vec<&(String, String, bool)> = self.vars.iter().collect();
+        vars.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        let mut out = String::new();
+        let mut quoted = false;
+        let mut rest = s;
+        while let Some(i) = rest.find('$') {
+            out.push_str(&rest[..i]);
+            rest = &rest[i..];
+            let found = vars.iter().find(|v| {
+                let name = v.0.as_str();
+                rest.starts_with(name) && !rest[name.len()..].starts_with(|c: char| c.is_alphanumeric() || c == '_')
+            });
+            match found {
+                Some(v) => {
+                    out.push_str(&v.1);
+                    quoted |= v.2;
+                    rest = &rest[v.0.len()..];
+                }
+                None => {
+                    out.push('$');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        out.push_str(rest);
+        (out, quoted)
+    }
 
-        let (name, args) = cmd.split_at(1);
-        match name[0] {
-            "exec" => {
-                println!("exec");
-                let mut cmd = build_cmd(args);
-                let handler = handler::exec(cmd);
-                self.bindsyms.insert(bind, handler);
+    // substitute variables in the tokens of a line, splitting unquoted
+    // words at the whitespace of unquoted values. The parts keep the
+    // offset of their word, a word taking a quoted value stays one
+    // quoted word. The name set gives a value stays as it is.
+    fn expand(&self, tokens: Vec<Token>, offsets: Vec<usize>) -> (Vec<Token>, Vec<usize>) {
+        let skip = if word(tokens.get(0)) == Some("set") { 2 } else { 0 };
+        let mut expanded = Vec::new();
+        let mut at = Vec::new();
+        for (i, (t, o)) in tokens.into_iter().zip(offsets.into_iter()).enumerate() {
+            if i < skip {
+                expanded.push(t);
+                at.push(o);
+                continue
             }
-            "layout" => {
-                println!("layout");
-                let layout = args[0];
-                match layout {
-                    "split" => {
-                        let handler = handler::layout(layout::Type::Tiling);
-                        self.bindsyms.insert(bind, handler);
+            match t {
+                Token::Word(w) => {
+                    let (value, quoted) = self.substitute_quoted(&w);
+                    if value == w {
+                        expanded.push(Token::Word(w));
+                        at.push(o);
+                        continue
                     }
-                    "tab" => {
-                        let handler = handler::layout(layout::Type::Tab);
-                        self.bindsyms.insert(bind, handler);
+                    if quoted {
+                        expanded.push(Token::Quoted(value));
+                        at.push(o);
+                        continue
                     }
-                    _ => {}
+                    for part in value.split_whitespace() {
+                        expanded.push(Token::Word(part.to_string()));
+                        at.push(o);
+                    }
+                }
+                Token::Quoted(w) => {
+                    expanded.push(Token::Quoted(self.substitute(&w)));
+                    at.push(o);
+                }
+                Token::Criteria(raw) => {
+                    expanded.push(Token::Criteria(self.substitute(&raw)));
+                    at.push(o);
+                }
+                t => {
+                    expanded.push(t);
+                    at.push(o);
                 }
             }
-            "fullscreen" => {
-                let handler = handler::fullscreen();
-                self.bindsyms.insert(bind, handler);
+        }
+        (expanded, at)
+    }
+
+    /// the errors in the file at path, without running anything
+    pub fn check(path: &Path) -> Vec<ConfigError> {
+        let mut config = Config::new();
+        config.load(Some(path))
+    }
+
+    fn error(&mut self, column: usize, message: String) {
+        self.errors.push(ConfigError {
+            file: self.file.clone(),
+            position: Some((self.line, column)),
+            message: message,
+        });
+    }
+
+    // mode commands switching to a mode that is never defined
+    fn check_modes(&mut self) {
+        for (name, err) in mem::replace(&mut self.mode_targets, Vec::new()) {
+            if name != DEFAULT_MODE && !self.modes.contains_key(&name) {
+                self.errors.push(err);
             }
-            "split" => {
-                let handler = handler::split_container();
-                self.bindsyms.insert(bind, handler);
+        }
+    }
+
+    fn file_error(&mut self, path: &Path, message: String) {
+        self.errors.push(ConfigError {
+            file: path.display().to_string(),
+            position: None,
+            message: message,
+        });
+    }
+
+    fn parse_line(&mut self, line: String) {
+        let text = line.trim();
+        // # is comment
+        if text.is_empty() || text.starts_with("#") {
+            return
+        }
+
+        // column of an offset in the untrimmed line, counting from 1
+        let lead = line[..line.len() - line.trim_left().len()].chars().count();
+        let column = |offset: usize| lead + text[..offset].chars().count() + 1;
+        match self.parse_tokens(text) {
+            Ok(modes) => {
+                for (name, offset) in modes {
+                    let err = ConfigError {
+                        file: self.file.clone(),
+                        position: Some((self.line, column(offset))),
+                        message: format!("mode {} is not defined", name),
+                    };
+                    self.mode_targets.push((name, err));
+                }
             }
-            "workspace" => {
-                println!("workspace");
-                let c = args[0].chars().nth(0);
-                match c {
-                    Some(v) => {
-                        let handler = handler::switch_workspace(v);
-                        self.bindsyms.insert(bind, handler);
+            Err((offset, message)) => { self.error(column(offset), message); }
+        }
+    }
+
+    // parse a trimmed line, giving the modes it switches to. Those and
+    // errors carry the byte offset they are at.
+    fn parse_tokens(&mut self, line: &str) -> Result<Vec<(String, usize)>, (usize, String)> {
+        let (tokens, offsets) = try!(command::tokenize_spans(line));
+        let (tokens, offsets) = self.expand(tokens, offsets);
+        let at = |index: usize| offsets.get(index).cloned().unwrap_or(line.len());
+        let result = self.parse_directive(&tokens);
+        let modes = mem::replace(&mut self.line_modes, Vec::new());
+        match result {
+            Ok(()) => { Ok(modes.into_iter().map(|(name, index)| (name, at(index))).collect()) }
+            Err((index, message)) => { Err((at(index), message)) }
+        }
+    }
+
+    // errors carry the index of the token they are at
+    fn parse_directive(&mut self, tokens: &[Token]) -> Result<(), (usize, String)> {
+        let name = match word(tokens.get(0)) {
+            Some(name) => { name }
+            None => { return Err((0, "expected a directive".to_string())) }
+        };
+        match name {
+            "set" => {
+                let name = match word(tokens.get(1)) {
+                    Some(name) if name.starts_with("$") && name.len() > 1 => { name }
+                    _ => { return Err((1, "set: expected a $variable".to_string())) }
+                };
+                let mut value = Vec::new();
+                let mut quoted = false;
+                for (i, t) in tokens.iter().enumerate().skip(2) {
+                    if let Token::Quoted(_) = *t {
+                        quoted = true;
                     }
-                    None => {}
+                    match word(Some(t)) {
+                        Some(w) => { value.push(w); }
+                        None => { return Err((i, "set: quote ; , and [ in values".to_string())) }
+                    }
+                }
+                if value.is_empty() {
+                    return Err((tokens.len(), "set: missing value".to_string()))
                 }
+                let value = value.join(" ");
+                self.set_var(name, &value, quoted);
             }
-            "window" => {
-                let c = args[0].chars().nth(0);
-                match c {
-                    Some(v) => {
-                        println!("window");
-                        let handler = handler::move_window_to_workspace(v);
-                        self.bindsyms.insert(bind, handler);
+            "include" => {
+                match word(tokens.get(1)) {
+                    Some(pattern) => {
+                        if let Err(err) = self.include(pattern) {
+                            return Err((1, err))
+                        }
                     }
-                    None => {}
+                    None => { return Err((1, "include: missing path".to_string())) }
                 }
             }
-            "resize" => {
-                let resize = match args[0] {
-                    "shrink" => {
-                        handler::Resize::Shrink
+            "exec" | "exec_always" => {
+                for (i, t) in tokens.iter().enumerate().skip(1) {
+                    if command::text(t).is_none() {
+                        return Err((i, format!("{}: quote ; , and [ in arguments", name)))
                     }
-                    "grow" => {
-                        handler::Resize::Grow
+                }
+                match command::parse_exec(&tokens[1..]) {
+                    Ok((args, workspace)) => {
+                        self.execs.push(Exec {
+                            args: args,
+                            workspace: workspace,
+                            always: name == "exec_always",
+                        });
                     }
-                    _ => { handler::Resize::Grow }
+                    Err(err) => { return Err((1, err)) }
+                }
+            }
+            "bind" => {
+                try!(self.bind_sym(tokens, false));
+            }
+            "bindcode" => {
+                try!(self.bind_sym(tokens, true));
+            }
+            "bindmouse" => {
+                try!(self.bind_mouse(tokens));
+            }
+            "for_window" => {
+                try!(self.for_window(tokens));
+            }
+            "assign" => {
+                try!(self.assign(tokens));
+            }
+            "focus_wrapping" => {
+                match word(tokens.get(1)) {
+                    Some("yes") => { self.focus_wrapping = true; }
+                    Some("no") => { self.focus_wrapping = false; }
+                    _ => { return Err((1, "focus_wrapping: expected yes or no".to_string())) }
+                }
+            }
+            "workspace" => {
+                let key = match word(tokens.get(1)) {
+                    Some(key) => { key.to_string() }
+                    None => { return Err((1, "workspace: missing workspace".to_string())) }
                 };
-                let direction = match args[1] {
-                    "width" => {
-                        LayoutDirection::Vertical
+                if word(tokens.get(2)) != Some("output") {
+                    return Err((2, "workspace: expected output".to_string()))
+                }
+                let mut names = Vec::new();
+                for (i, t) in tokens.iter().enumerate().skip(3) {
+                    match word(Some(t)) {
+                        Some(name) => { names.push(name.to_string()); }
+                        None => { return Err((i, "workspace: expected an output name".to_string())) }
                     }
-                    "height" => {
-                        LayoutDirection::Horizontal
+                }
+                if names.is_empty() {
+                    return Err((3, "workspace: missing output".to_string()))
+                }
+                self.output_assigns.push((key, names));
+            }
+            "chord_timeout" => {
+                match word(tokens.get(1)).and_then(|v| v.parse().ok()) {
+                    Some(v) => { self.chord_timeout = v; }
+                    None => { return Err((1, "chord_timeout: expected milliseconds".to_string())) }
+                }
+            }
+            "mode" => {
+                match (word(tokens.get(1)), word(tokens.get(2))) {
+                    (Some(name), Some("{")) => {
+                        self.modes.entry(name.to_string()).or_insert(HashMap::new());
+                        self.block = Some(name.to_string());
                     }
-                    _ => { LayoutDirection::Horizontal }
-                };
-                let handler = handler::resize_window(direction, resize);
-                self.bindsyms.insert(bind, handler);
-            }
-            "focus" => {
-                let direction = match args[0] {
-                    "left" => MoveDirection::Left,
-                    "right" => MoveDirection::Right,
-                    "up" => MoveDirection::Up,
-                    "down" => MoveDirection::Down,
-                    _ => MoveDirection::Right
-                };
-                let handler = handler::focus_window(direction);
-                self.bindsyms.insert(bind, handler);
+                    _ => { return Err((1, "mode: expected a name and {".to_string())) }
+                }
+            }
+            "}" => {
+                if self.block.take().is_none() {
+                    return Err((0, "} without mode".to_string()))
+                }
             }
-            "kill" => {
-                let handler = handler::close_window();
-                self.bindsyms.insert(bind, handler);
+            _ => {
+                return Err((0, format!("unknown directive {}", name)))
             }
-            _ => {}
+        }
+        Ok(())
+    }
+
+    // bind [--release] keys... command
+    fn bind_sym(&mut self, tokens: &[Token], by_code: bool) -> Result<(), (usize, String)> {
+        let release = word(tokens.get(1)) == Some("--release");
+        let first = if release { 2 } else { 1 };
+
+        // leading tokens that are key combinations form the key sequence,
+        // e.g. bind $mod+x w 3 workspace 3
+        let mod_key = self.mod_key;
+        let mut keys: Vec<KeyBind> = tokens[first..].iter()
+            .map(|t| {
+                match word(Some(t)) {
+                    Some(t) if by_code => { KeyBind::parse_code(mod_key, t) }
+                    Some(t) => { KeyBind::parse(mod_key, t) }
+                    None => { None }
+                }
+            })
+            .take_while(|b| b.is_some())
+            .map(|b| b.unwrap())
+            .collect();
+        if keys.is_empty() {
+            let key = word(tokens.get(first)).unwrap_or("");
+            return Err((first, format!("invalid key {}", key)))
+        }
+        let start = first + keys.len();
+        if start == tokens.len() {
+            return Err((start, "missing command".to_string()))
+        }
+        if let Some(last) = keys.last_mut() {
+            last.release = release;
+        }
+
+        let handler = try!(self.build_handler(tokens, start));
+        let binds = match self.block {
+            Some(ref name) => {
+                self.modes.entry(name.clone()).or_insert(HashMap::new())
+            }
+            None => {
+                &mut self.bindsyms
+            }
+        };
+        if Binding::insert(binds, &keys, handler) {
+            return Err((first, "key already bound, this binding replaces it".to_string()))
+        }
+        Ok(())
+    }
+
+    // bindmouse [modifiers+]buttonN titlebar|window|root|border command
+    fn bind_mouse(&mut self, tokens: &[Token]) -> Result<(), (usize, String)> {
+        if tokens.len() < 4 {
+            return Err((tokens.len(), "bindmouse: expected a button, a target and a command".to_string()))
+        }
+        let bind = match (word(tokens.get(1)), word(tokens.get(2))) {
+            (Some(button), Some(target)) => { MouseBind::parse(self.mod_key, button, target) }
+            _ => { None }
         };
+        match bind {
+            Some(bind) => {
+                let handler = try!(self.build_handler(tokens, 3));
+                if self.mousebinds.insert(bind, handler).is_some() {
+                    return Err((1, "button already bound, this binding replaces it".to_string()))
+                }
+                Ok(())
+            }
+            None => { Err((1, "bindmouse: invalid button or target".to_string())) }
+        }
+    }
+
+    // the criteria token at index
+    fn criteria(tokens: &[Token], index: usize) -> Result<Criteria, (usize, String)> {
+        match tokens.get(index) {
+            Some(&Token::Criteria(ref raw)) => {
+                match Criteria::parse(raw) {
+                    Some((criteria, _)) => { Ok(criteria) }
+                    None => { Err((index, format!("invalid criteria {}", raw))) }
+                }
+            }
+            _ => { Err((index, "expected [criteria]".to_string())) }
+        }
     }
 
-    fn set_var(&mut self, key: &str, val: &str) {
-        match key {
+    // for_window [criteria] command
+    fn for_window(&mut self, tokens: &[Token]) -> Result<(), (usize, String)> {
+        let criteria = try!(Config::criteria(tokens, 1));
+        let handler = try!(self.build_handler(tokens, 2));
+        self.rules.push(Rule {
+            criteria: criteria,
+            handler: handler,
+        });
+        Ok(())
+    }
+
+    // assign [--switch] [criteria] [workspace] 2
+    fn assign(&mut self, tokens: &[Token]) -> Result<(), (usize, String)> {
+        let switch = word(tokens.get(1)) == Some("--switch");
+        let index = if switch { 2 } else { 1 };
+        let criteria = try!(Config::criteria(tokens, index));
+        let index = if word(tokens.get(index + 1)) == Some("workspace") { index + 2 } else { index + 1 };
+        // an unquoted name takes the rest of the line
+        let mut words = Vec::new();
+        for (i, t) in tokens.iter().enumerate().skip(index) {
+            match word(Some(t)) {
+                Some(w) => { words.push(w); }
+                None => { return Err((i, "assign: expected a workspace name".to_string())) }
+            }
+        }
+        if words.is_empty() {
+            return Err((index, "assign: missing workspace".to_string()))
+        }
+        self.assigns.push(Assign {
+            criteria: criteria,
+            workspace: words.join(" "),
+            switch: switch,
+        });
+        Ok(())
+    }
+
+    // one handler running the actions of the command in tokens[start..]
+    fn build_handler(&mut self, tokens: &[Token], start: usize) -> Result<handler::HandleFn, (usize, String)> {
+        let actions = match command::parse(&tokens[start..]) {
+            Ok(actions) => { actions }
+            Err((index, message)) => { return Err((start + index, message)) }
+        };
+        // remember the modes switched to, at the name after each mode
+        let mut from = start;
+        for action in actions.iter() {
+            for c in action.commands.iter() {
+                if let Command::Mode(ref name) = *c {
+                    let index = (from..tokens.len())
+                        .find(|&i| word(tokens.get(i)) == Some("mode") && word(tokens.get(i + 1)) == Some(name.as_str()))
+                        .map(|i| i + 1)
+                        .unwrap_or(start);
+                    from = index + 1;
+                    self.line_modes.push((name.clone(), index));
+                }
+            }
+        }
+        let mut handlers = Vec::new();
+        for action in actions {
+            let chain = handler::chain(action.commands.into_iter()
+                                       .map(|c| self.command_handler(c))
+                                       .collect());
+            match action.criteria {
+                Some(criteria) => { handlers.push(handler::with_criteria(criteria, chain)); }
+                None => { handlers.push(chain); }
+            }
+        }
+        Ok(handler::chain(handlers))
+    }
+
+    fn command_handler(&self, cmd: Command) -> handler::HandleFn {
+        match cmd {
+            Command::Exec(args, workspace) => {
+                let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+                handler::exec(build_cmd(&args), &args[0], workspace)
+            }
+            Command::Layout(layout) => { handler::layout(layout) }
+            Command::Fullscreen => { handler::fullscreen() }
+            Command::Split => { handler::split_container() }
+            Command::Workspace(WorkspaceTarget::Next) => { handler::cycle_workspace(true) }
+            Command::Workspace(WorkspaceTarget::Prev) => { handler::cycle_workspace(false) }
+            Command::Workspace(WorkspaceTarget::Name(key)) => { handler::switch_workspace(key) }
+            Command::MoveToWorkspace(key) => { handler::move_window_to_workspace(key) }
+            Command::Rename(old, new) => { handler::rename_workspace(old, new) }
+            Command::Resize(direction, resize) => { handler::resize_window(direction, resize) }
+            Command::Focus(FocusTarget::Direction(direction)) => { handler::focus_window(direction) }
+            Command::Focus(FocusTarget::Parent) => { handler::focus_parent() }
+            Command::Focus(FocusTarget::Child) => { handler::focus_child() }
+            Command::Focus(FocusTarget::Last) => { handler::focus_last() }
+            Command::Focus(FocusTarget::Mru(forward)) => { handler::cycle_focus(forward) }
+            Command::Focus(FocusTarget::Target) => { handler::focus_target() }
+            Command::Focus(FocusTarget::Output(target)) => { handler::focus_output(target) }
+            Command::MoveWorkspaceToOutput(target) => { handler::move_workspace_to_output(target) }
+            Command::Move(direction) => { handler::move_direction(direction) }
+            Command::Kill => { handler::close_window() }
+            Command::Floating(Floating::Enable) => { handler::floating(Some(true)) }
+            Command::Floating(Floating::Disable) => { handler::floating(Some(false)) }
+            Command::Floating(Floating::Toggle) => { handler::floating(None) }
+            Command::Border(Border::None) => { handler::border(0) }
+            Command::Border(Border::Normal) => { handler::border(self.titlebar_height) }
+            Command::Mode(name) => { handler::switch_mode(name) }
+            Command::Reload => { handler::reload() }
+        }
+    }
+
+    // variables are substituted in the lines that follow, $mod also
+    // picks the modifier of bindings
+    fn set_var(&mut self, key: &str, val: &str, quoted: bool) {
+        let val = match key {
             "$mod" => {
-                self.mod_key = match val {
-                    "Shift" => xlib::ShiftMask,
-                    "Ctrl"  => xlib::ControlMask,
-                    "Mod1"  => xlib::Mod1Mask,
-                    "Mod2" => xlib::Mod2Mask,
-                    "Mod3" => xlib::Mod3Mask,
-                    "Mod4" => xlib::Mod4Mask,
-                    "Mod5" => xlib::Mod5Mask,
-                    _ => xlib::Mod4Mask
+                let (mask, name) = match val {
+                    "Shift" => (xlib::ShiftMask, "Shift"),
+                    "Ctrl"  => (xlib::ControlMask, "Ctrl"),
+                    "Mod1"  => (xlib::Mod1Mask, "Mod1"),
+                    "Mod2" => (xlib::Mod2Mask, "Mod2"),
+                    "Mod3" => (xlib::Mod3Mask, "Mod3"),
+                    "Mod4" => (xlib::Mod4Mask, "Mod4"),
+                    "Mod5" => (xlib::Mod5Mask, "Mod5"),
+                    _ => (xlib::Mod4Mask, "Mod4")
                 };
+                self.mod_key = mask;
+                name
             }
-            _ => {}
-        }
+            _ => { val }
+        };
+        self.vars.retain(|v| v.0 != key);
+        self.vars.push((key.to_string(), val.to_string(), quoted));
     }
 }
 
@@ -220,13 +750,296 @@ fn test_map() {
     let mut bindsyms: HashMap<KeyBind, i32> = HashMap::new();
     let b = KeyBind {
         key: 0,
-        mask: 0
+        code: 0,
+        mask: 0,
+        release: false,
     };
     bindsyms.insert(b, 1);
 
     let c = KeyBind {
         key: 0,
-        mask: 0
+        code: 0,
+        mask: 0,
+        release: false,
     };
     assert!(bindsyms.contains_key(&c), true);
 }
+
+#[test]
+fn test_mode_block() {
+    let mut config = Config::new();
+    config.parse_line("mode \"resize\" {".to_string());
+    config.parse_line("    bind Escape mode default".to_string());
+    config.parse_line("}".to_string());
+    config.parse_line("bind Mod4+r mode resize".to_string());
+    assert!(config.modes.contains_key("resize"));
+    assert_eq!(config.modes.get("resize").unwrap().len(), 1);
+    assert_eq!(config.bindsyms.len(), 1);
+
+    // switching to a mode that is never defined is an error, once the
+    // whole config is read
+    config.parse_line("bind Mod4+t mode \"rezise\"".to_string());
+    config.parse_line("bind Mod4+u layout tab, mode resize".to_string());
+    config.parse_line("mode empty {".to_string());
+    config.parse_line("}".to_string());
+    config.parse_line("bind Mod4+e mode empty".to_string());
+    assert!(config.errors.is_empty());
+    config.check_modes();
+    assert_eq!(config.errors.len(), 1);
+    assert_eq!(config.errors[0].position.unwrap().1, 18);
+    assert_eq!(config.errors[0].message, "mode rezise is not defined");
+}
+
+#[test]
+fn test_chord() {
+    let mut config = Config::new();
+    config.parse_line("bind Mod4+x w 3 workspace 3".to_string());
+    config.parse_line("bind Mod4+x w 4 workspace 4".to_string());
+    assert_eq!(config.bindsyms.len(), 1);
+    assert!(config.errors.is_empty());
+
+    let keys: Vec<KeyBind> = ["Mod4+x", "w"].iter()
+        .map(|t| KeyBind::parse(0, t).unwrap())
+        .collect();
+    match config.get_binding(DEFAULT_MODE, &keys) {
+        Some(&mut Binding::Prefix(ref next)) => { assert_eq!(next.len(), 2); }
+        _ => { panic!("w should be a prefix") }
+    }
+}
+
+#[test]
+fn test_duplicate_bindings() {
+    let mut config = Config::new();
+    config.parse_line("bind Mod4+v split".to_string());
+    config.parse_line("bind  Mod4+v kill".to_string());
+    config.parse_line("bind Mod4+x kill".to_string());
+    config.parse_line("bind Mod4+x w 1 workspace 1".to_string());
+    config.parse_line("bindmouse button2 titlebar kill".to_string());
+    config.parse_line("bindmouse button2 titlebar split".to_string());
+    // the later binding wins, but is reported
+    assert_eq!(config.bindsyms.len(), 2);
+    assert_eq!(config.mousebinds.len(), 1);
+    let columns: Vec<usize> = config.errors.iter().map(|e| e.position.unwrap().1).collect();
+    assert_eq!(columns, vec![7, 6, 11]);
+}
+
+#[test]
+fn test_bindcode_release() {
+    let mut config = Config::new();
+    config.parse_line("bindcode --release Mod4+24 kill".to_string());
+    let bind = config.bindsyms.keys().next().unwrap();
+    assert_eq!(bind.code, 24);
+    assert_eq!(bind.mask, xlib::Mod4Mask);
+    assert!(bind.release);
+}
+
+#[test]
+fn test_bindmouse() {
+    let mut config = Config::new();
+    config.parse_line("bindmouse Mod4+button4 root workspace next".to_string());
+    config.parse_line("bindmouse button2 titlebar kill".to_string());
+    config.parse_line("bindmouse button2 nowhere kill".to_string());
+    assert_eq!(config.mousebinds.len(), 2);
+
+    let bind = MouseBind::parse(0, "Mod4+button4", "root").unwrap();
+    assert_eq!(bind.button, 4);
+    assert_eq!(bind.mask, xlib::Mod4Mask);
+    assert!(config.mousebinds.contains_key(&bind));
+}
+
+#[test]
+fn test_for_window() {
+    use super::criteria::WindowInfo;
+
+    let mut config = Config::new();
+    config.parse_line("for_window [class=\"^Firefox$\" title=\"Private Browsing\"] move to workspace 2".to_string());
+    config.parse_line("for_window [window_type=dialog] floating enable".to_string());
+    config.parse_line("for_window [class=\"(\"] kill".to_string());
+    config.parse_line("for_window [] kill".to_string());
+    assert_eq!(config.rules.len(), 2);
+
+    let mut info = WindowInfo {
+        class: Some("Firefox".to_string()),
+        instance: Some("Navigator".to_string()),
+        role: None,
+        title: Some("Mozilla Firefox".to_string()),
+        window_type: vec!["normal".to_string()],
+    };
+    assert!(!config.rules[0].criteria.matches(&info));
+    assert!(!config.rules[1].criteria.matches(&info));
+    info.title = Some("Mozilla Firefox Private Browsing".to_string());
+    assert!(config.rules[0].criteria.matches(&info));
+
+    config.parse_line("for_window [class=\"^mpv$\"] floating disable".to_string());
+    config.parse_line("for_window [class=\"^mpv$\"] floating toggle".to_string());
+    config.parse_line("for_window [class=\"^mpv$\"] floating sideways".to_string());
+    assert_eq!(config.rules.len(), 4);
+}
+
+#[test]
+fn test_assign() {
+    let mut config = Config::new();
+    config.parse_line("assign [class=\"Gimp\"] workspace 3".to_string());
+    config.parse_line("assign --switch [instance=\"irssi\"] 9".to_string());
+    config.parse_line("assign [class=\"Gimp\"]".to_string());
+    config.parse_line("assign [class=\"Firefox\"] 2: web".to_string());
+    assert_eq!(config.assigns.len(), 3);
+    assert_eq!(config.assigns[2].workspace, "2: web");
+    assert_eq!(config.assigns[0].workspace, "3");
+    assert!(!config.assigns[0].switch);
+    assert_eq!(config.assigns[1].workspace, "9");
+    assert!(config.assigns[1].switch);
+}
+
+#[test]
+fn test_criteria_command() {
+    let mut config = Config::new();
+    config.parse_line("bind Mod4+m [class=\"mpv\" title=\"some video\"] focus".to_string());
+    config.parse_line("bind Mod4+n [class=\"mpv\"]".to_string());
+    config.parse_line("bind Mod4+b [title=\"a b\" kill".to_string());
+    config.parse_line("bind   Mod4+q\tkill, workspace 2".to_string());
+    assert_eq!(config.bindsyms.len(), 2);
+}
+
+#[test]
+fn test_errors() {
+    let mut config = Config::new();
+    config.parse_line("bind Mod4+nosuchkey kill".to_string());
+    config.parse_line("  bind Mod4+q".to_string());
+    config.parse_line("bind Mod4+q kill, layout diagonal".to_string());
+    config.parse_line("frobnicate".to_string());
+    config.parse_line("exec \"unterminated".to_string());
+    config.parse_line("}".to_string());
+    config.parse_line("bind Mod4+w kill".to_string());
+    assert_eq!(config.bindsyms.len(), 1);
+
+    let columns: Vec<usize> = config.errors.iter().map(|e| e.position.unwrap().1).collect();
+    assert_eq!(columns, vec![6, 14, 19, 1, 6, 1]);
+}
+
+#[test]
+fn test_include_and_vars() {
+    use std::fs;
+
+    let dir = env::temp_dir().join("rustile_test_include");
+    fs::create_dir_all(dir.join("conf.d")).unwrap();
+    File::create(dir.join("config")).unwrap()
+        .write_all(b"set $ws 3\nset $left h\ninclude conf.d/*.conf\ninclude config\n").unwrap();
+    File::create(dir.join("conf.d/a.conf")).unwrap()
+        .write_all(b"bind Mod4+$left workspace $ws\nbind Mod4+$nope kill\n").unwrap();
+
+    let errors = Config::check(&dir.join("config"));
+    assert_eq!(errors.len(), 2);
+    assert!(errors[0].file.ends_with("a.conf"));
+    assert_eq!(errors[0].position, Some((2, 6)));
+    assert_eq!(errors[1].position, Some((4, 9)));
+
+    // a missing file has no position but its path
+    let missing = dir.join("missing");
+    let errors = Config::check(&missing);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].position, None);
+    assert!(errors[0].to_string().starts_with(&format!("{}: ", missing.display())));
+
+    let mut config = Config::new();
+    config.load(Some(&dir.join("config")));
+    let keys = vec![KeyBind::parse(0, "Mod4+h").unwrap()];
+    assert!(config.get_binding(DEFAULT_MODE, &keys).is_some());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_vars() {
+    let mut config = Config::new();
+    config.parse_line("set $m h".to_string());
+    config.parse_line("bind $mod+$m kill".to_string());
+    config.parse_line("set $mod Mod1".to_string());
+    config.parse_line("set $move move left".to_string());
+    config.parse_line("bind $mod+$m $move".to_string());
+    config.parse_line("set $ws \"2: $m\"".to_string());
+    config.line = 7;
+    config.parse_line("bind $mod+w workspace $ws, nosuch $m".to_string());
+    assert_eq!(config.bindsyms.len(), 2);
+    for keys in ["Mod4+h", "Mod1+h"].iter() {
+        let keys = vec![KeyBind::parse(0, keys).unwrap()];
+        assert!(config.get_binding(DEFAULT_MODE, &keys).is_some());
+    }
+    assert_eq!(config.substitute("$ws $wsx $mod$"), "2: h $wsx Mod1$");
+
+    // the column is the one of the line as written
+    assert_eq!(config.errors.len(), 1);
+    assert_eq!(config.errors[0].position, Some((7, 28)));
+
+    // a quoted value stays one word, an unquoted one is split
+    for &(line, name) in [("workspace $ws", "2: h"), ("move to workspace x$ws", "x2: h")].iter() {
+        let (tokens, offsets) = command::tokenize_spans(line).unwrap();
+        let (tokens, _) = config.expand(tokens, offsets);
+        match command::parse(&tokens).unwrap()[0].commands[0] {
+            Command::Workspace(WorkspaceTarget::Name(ref key)) => { assert_eq!(key, name); }
+            Command::MoveToWorkspace(ref key) => { assert_eq!(key, name); }
+            _ => { panic!("expected a workspace") }
+        }
+    }
+    config.parse_line("exec --workspace $ws $move".to_string());
+    assert_eq!(config.execs[0].workspace, Some("2: h".to_string()));
+    assert_eq!(config.execs[0].args, vec!["move", "left"]);
+}
+
+#[test]
+fn test_exec() {
+    let mut config = Config::new();
+    config.parse_line("exec pwd".to_string());
+    config.parse_line("exec_always --workspace 3 xterm -e top".to_string());
+    config.parse_line("exec --workspace".to_string());
+    config.parse_line("exec_always".to_string());
+    assert_eq!(config.execs.len(), 2);
+    assert!(!config.execs[0].always);
+    assert_eq!(config.execs[0].workspace, None);
+    assert!(config.execs[1].always);
+    assert_eq!(config.execs[1].workspace, Some("3".to_string()));
+    assert_eq!(config.execs[1].args, vec!["xterm", "-e", "top"]);
+    assert_eq!(config.errors.len(), 2);
+}
+
+#[test]
+fn test_build_cmd() {
+    use std::ffi::OsStr;
+
+    let mut config = Config::new();
+    config.parse_line("exec notify-send \"x; y\" it\\'s $HOME \"a|b\"".to_string());
+    config.parse_line("exec \"ls | wc -l\"".to_string());
+    config.parse_line("exec ls | wc -l".to_string());
+    let lines: Vec<String> = config.execs.iter().map(|e| {
+        let args: Vec<&str> = e.args.iter().map(|a| a.as_str()).collect();
+        let cmd = build_cmd(&args);
+        let args: Vec<&OsStr> = cmd.get_args().collect();
+        assert_eq!(args[0], "-c");
+        args[1].to_string_lossy().into_owned()
+    }).collect();
+    assert_eq!(lines, vec!["notify-send 'x; y' 'it'\\''s' $HOME 'a|b'", "ls | wc -l", "ls | wc -l"]);
+}
+
+#[test]
+fn test_outputs() {
+    let mut config = Config::new();
+    config.parse_line("workspace 1 output HDMI-1 eDP-1".to_string());
+    config.parse_line("workspace 2 output".to_string());
+    config.parse_line("bind Mod4+o focus output right".to_string());
+    config.parse_line("bind Mod4+Shift+o move workspace to output HDMI-1".to_string());
+    config.parse_line("bind Mod4+p move workspace to output".to_string());
+    assert_eq!(config.output_assigns, vec![("1".to_string(), vec!["HDMI-1".to_string(), "eDP-1".to_string()])]);
+    assert_eq!(config.bindsyms.len(), 2);
+    assert_eq!(config.errors.len(), 2);
+}
+
+#[test]
+fn test_workspace_names() {
+    let mut config = Config::new();
+    config.parse_line("bind Mod4+w workspace \"1: web\"".to_string());
+    config.parse_line("bind Mod4+m move to workspace mail".to_string());
+    config.parse_line("bind Mod4+r rename workspace to music".to_string());
+    config.parse_line("bind Mod4+Shift+r rename workspace 10 to \"10: chat\"".to_string());
+    config.parse_line("bind Mod4+t rename workspace 10".to_string());
+    assert_eq!(config.bindsyms.len(), 4);
+    assert_eq!(config.errors.len(), 1);
+}
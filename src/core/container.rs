@@ -3,9 +3,14 @@ extern crate x11;
 
 use x11::xlib;
 use std::ptr;
-use super::layout::{ self, Rectangle, LayoutDirection, MoveDirection };
+use std::mem;
+use super::layout::{ self, Rectangle, LayoutDirection, MoveDirection, DropZone };
+use super::canvas::{ Canvas, Decoration };
 use super::super::libx;
 
+// smallest width or height a client can be resized to
+const MIN_SIZE: u32 = 32;
+
 #[derive(Copy, Clone)]
 pub enum Mode {
     Normal,
@@ -26,11 +31,18 @@ pub struct Container {
     pub titlebar_height: u32,
     parent: *mut Container,
     pub clients: Vec<Container>,
+    // windows floating above the clients, only workspaces have them
+    pub floating: Vec<Container>,
+    // ids of clients, most recently focused first
+    pub focus_stack: Vec<xlib::Window>,
     pub mode: Mode,
+    // selected with focus parent, drawn highlighted
+    pub selected: bool,
     pub category: Type,
     pub titlebar: Option<Rectangle>,
     pub portion: f32,
     pub context: libx::Context,
+    pub canvas: Option<Canvas>,
 
     pub layout: layout::Type,
     pub direction: LayoutDirection,
@@ -61,14 +73,19 @@ impl Container {
                                      attrs.x, attrs.y,
                                      attrs.width as u32,
                                      attrs.height as u32);
-        let mask = xlib::SubstructureNotifyMask | xlib::SubstructureRedirectMask | xlib::ButtonPressMask | xlib::ButtonReleaseMask | xlib::Button1MotionMask | xlib::Button3MotionMask | xlib::ExposureMask;
+        let mask = xlib::SubstructureNotifyMask | xlib::SubstructureRedirectMask | xlib::ButtonPressMask | xlib::ButtonReleaseMask | xlib::PointerMotionMask | xlib::LeaveWindowMask | xlib::ExposureMask;
         libx::select_input(context, id, mask);
+        let canvas = Canvas::new(context, id, attrs.width as u32, attrs.height as u32);
         Container {
             context: context,
+            canvas: Some(canvas),
             clients: Vec::new(),
+            floating: Vec::new(),
             visible: false,
             id: Some(id),
+            focus_stack: Vec::new(),
             mode: Mode::Normal,
+            selected: false,
             category: Type::Container,
             parent: ptr::null_mut(),
             titlebar: None,
@@ -83,10 +100,14 @@ impl Container {
     pub fn from_id(context: libx::Context, id: xlib::Window) -> Container {
         Container {
             context: context,
+            canvas: None,
             clients: Vec::new(),
+            floating: Vec::new(),
             visible: false,
             id: Some(id),
+            focus_stack: Vec::new(),
             mode: Mode::Normal,
+            selected: false,
             category: Type::App,
             parent: ptr::null_mut(),
             titlebar: None,
@@ -150,6 +171,19 @@ impl Container {
         }
     }
 
+    /// point the parent of every container in the tree back to its owner,
+    /// needed after clients moved in memory
+    pub fn relink(&mut self) {
+        let p: *mut Container = self;
+        for client in self.clients.iter_mut() {
+            client.parent = p;
+            client.relink();
+        }
+        for client in self.floating.iter_mut() {
+            client.parent = p;
+        }
+    }
+
     pub fn add(&mut self, mut client: Container) {
         self.be_parent(&mut client);
         let portion = 1.0 / (self.size() as f32 + 1.0);
@@ -158,6 +192,7 @@ impl Container {
         }
         client.portion = portion;
         self.clients.push(client);
+        self.relink();
     }
 
     pub fn insert(&mut self, index: usize,  mut client: Container) {
@@ -168,6 +203,7 @@ impl Container {
             client.portion = client.portion * (1.0-portion);
         }
         self.clients.insert(index, client);
+        self.relink();
     }
 
     pub fn remove(&mut self, id: xlib::Window) -> Option<Container>{
@@ -177,9 +213,51 @@ impl Container {
                 self.remove_by_index(index)
             }
             None => {
-                None
+                self.remove_floating(id)
+            }
+        }
+    }
+
+    /// put client above the clients at x, y, keeping its size
+    pub fn add_floating(&mut self, mut client: Container, x: i32, y: i32) {
+        let rec = client.rec();
+        libx::reparent(self.context, client.raw_id(), self.raw_id(), x, y);
+        client.configure(x, y, rec.width, rec.height);
+        libx::raise_window(self.context, client.raw_id());
+        self.floating.push(client);
+        self.relink();
+    }
+
+    fn remove_floating(&mut self, id: xlib::Window) -> Option<Container> {
+        match self.floating.iter().position(|c| c.raw_id() == id) {
+            Some(index) => {
+                let mut r = self.floating.remove(index);
+                r.parent = ptr::null_mut();
+                self.focus_stack.retain(|&w| w != id);
+                self.relink();
+                Some(r)
+            }
+            None => { None }
+        }
+    }
+
+    pub fn is_floating(&self) -> bool {
+        match self.get_parent() {
+            Some(p) => { p.floating.iter().any(|c| c.raw_id() == self.raw_id()) }
+            None => { false }
+        }
+    }
+
+    /// keep the floating windows above the clients, top above the others
+    pub fn raise_floating(&self, top: xlib::Window) {
+        for client in self.floating.iter() {
+            if client.raw_id() != top {
+                libx::raise_window(self.context, client.raw_id());
             }
         }
+        if self.floating.iter().any(|c| c.raw_id() == top) {
+            libx::raise_window(self.context, top);
+        }
     }
 
     fn remove_by_index(&mut self, index: usize) -> Option<Container>{
@@ -187,7 +265,11 @@ impl Container {
             None
         }
         else{
-            let r = self.clients.remove(index);
+            let mut r = self.clients.remove(index);
+            r.parent = ptr::null_mut();
+            let id = r.raw_id();
+            self.focus_stack.retain(|&w| w != id);
+            self.relink();
             let portion = 1.0 - r.portion;
             for client in self.clients.iter_mut() {
                 client.portion = client.portion / portion;
@@ -262,38 +344,108 @@ impl Container {
                 return r
             }
         }
+        self.floating.iter_mut().find(|c| c.raw_id() == id)
+    }
+
+    pub fn has_window(&self, id: xlib::Window) -> bool {
+        self.raw_id() == id || self.clients.iter().chain(self.floating.iter()).any(|c| c.has_window(id))
+    }
+
+    /// the client whose subtree holds id
+    pub fn child_towards(&self, id: xlib::Window) -> Option<&Container> {
+        self.clients.iter().find(|c| c.has_window(id))
+    }
+
+    pub fn push_focus(&mut self, id: xlib::Window) {
+        self.focus_stack.retain(|&w| w != id);
+        self.focus_stack.insert(0, id);
+    }
+
+    /// the App window in this subtree that had focus last, following
+    /// the focus stack down and falling back to the first window
+    pub fn last_focused(&self) -> Option<xlib::Window> {
+        if self.is_app() {
+            return Some(self.raw_id())
+        }
+        for id in self.focus_stack.iter() {
+            if let Some(c) = self.clients.iter().chain(self.floating.iter()).find(|c| c.raw_id() == *id) {
+                if let Some(w) = c.last_focused() {
+                    return Some(w)
+                }
+            }
+        }
+        for c in self.clients.iter() {
+            if let Some(w) = c.last_focused() {
+                return Some(w)
+            }
+        }
         None
     }
 
+    /// call f on this container and everything below it
+    pub fn visit(&mut self, f: &mut FnMut(&mut Container)) {
+        f(self);
+        for client in self.clients.iter_mut().chain(self.floating.iter_mut()) {
+            client.visit(f);
+        }
+    }
+
+    /// all App windows in this subtree
+    pub fn leaves(&self) -> Vec<xlib::Window> {
+        if self.is_app() {
+            return vec![self.raw_id()]
+        }
+        let mut leaves = Vec::new();
+        for client in self.clients.iter().chain(self.floating.iter()) {
+            leaves.extend(client.leaves());
+        }
+        leaves
+    }
+
     pub fn contain(&self, id: xlib::Window) -> Option<usize>{
         self.clients.iter().position(|x| (*x).raw_id() == id)
     }
 
     pub fn configure(&mut self, x: i32, y: i32, width: u32, height: u32) {
         libx::resize_window(self.context, self.raw_id(), x, y, width, height);
+        if let Some(canvas) = self.canvas.as_mut() {
+            canvas.resize(width, height);
+        }
         // layout for children clients
         self.update_layout();
     }
 
-    pub fn resize_children(&mut self, index: usize, neighbor: usize, step: f32) {
+    /// move step of the portion from neighbor to index, clamped so that
+    /// no client gets smaller than MIN_SIZE, returns the step applied
+    pub fn resize_children(&mut self, index: usize, neighbor: usize, step: f32) -> f32 {
         let size = self.size();
-        if index >= 0 && index < size && neighbor >= 0 && neighbor < size {
-            {
-                let a = self.clients.get(index).unwrap();
-                let b = self.clients.get(neighbor).unwrap();
-                if (a.portion + step) <= 0.0 || (b.portion - step) < 0.0 {
-                    return
-                }
-            }
+        if index >= size || neighbor >= size {
+            return 0.0
+        }
 
-            {
-                let mut a = self.clients.get_mut(index).unwrap();
-                a.portion = a.portion + step;
-            }
-            {
-                let mut b = self.clients.get_mut(neighbor).unwrap();
-                b.portion = b.portion - step;
-            }
+        let min = self.min_portion();
+        let a = self.clients[index].portion;
+        let b = self.clients[neighbor].portion;
+        let lower = (min - a).min(0.0);
+        let upper = (b - min).max(0.0);
+        let step = step.max(lower).min(upper);
+
+        self.clients[index].portion = a + step;
+        self.clients[neighbor].portion = b - step;
+        step
+    }
+
+    fn min_portion(&self) -> f32 {
+        let rec = self.rec();
+        let extent = match self.direction {
+            LayoutDirection::Vertical => { rec.height }
+            LayoutDirection::Horizontal => { rec.width }
+        };
+        if extent == 0 {
+            0.0
+        }
+        else {
+            MIN_SIZE as f32 / extent as f32
         }
     }
 
@@ -304,14 +456,14 @@ impl Container {
     pub fn map(&self) {
         // self.visible = true;
         libx::map_window(self.context, self.raw_id());
-        for client in self.clients.iter() {
+        for client in self.clients.iter().chain(self.floating.iter()) {
             client.map();
         }
     }
 
     pub fn unmap(&self) {
         // self.visible = false;
-        for client in self.clients.iter() {
+        for client in self.clients.iter().chain(self.floating.iter()) {
             client.unmap();
         }
         libx::unmap_window(self.context, self.raw_id());
@@ -360,6 +512,21 @@ impl Container {
         }
     }
 
+    /// the direction clients are placed along, tabs count as horizontal
+    pub fn orientation(&self) -> LayoutDirection {
+        match self.layout {
+            layout::Type::Tab => LayoutDirection::Horizontal,
+            layout::Type::Tiling => self.direction.clone(),
+        }
+    }
+
+    pub fn is_app(&self) -> bool {
+        match self.category {
+            Type::App => true,
+            _ => false,
+        }
+    }
+
     pub fn change_layout(&mut self, layout_type: layout::Type) {
         if self.layout == layout_type {
             match self.direction {
@@ -378,7 +545,7 @@ impl Container {
     }
 
     pub fn split(&mut self) -> bool {
-        if !self.is_empty() || self.raw_id() == self.context.root {
+        if !self.is_empty() || self.raw_id() == self.context.root || self.is_floating() {
             return false;
         }
 
@@ -400,7 +567,19 @@ impl Container {
         let id = self.id;
         self.id = container.id;
         container.id = id;
+        mem::swap(&mut self.canvas, &mut container.canvas);
+        mem::swap(&mut self.category, &mut container.category);
         container.titlebar_height = self.titlebar_height;
+        // the parent remembers the new container in place of the window
+        let (old_id, new_id) = (container.raw_id(), self.raw_id());
+        if let Some(p) = self.get_parent() {
+            for w in p.focus_stack.iter_mut() {
+                if *w == old_id {
+                    *w = new_id;
+                }
+            }
+        }
+        self.focus_stack = vec![old_id];
         self.add(container);
         self.map();
         true
@@ -429,26 +608,46 @@ impl Container {
     }
 
     pub fn decorate(&self, focused: bool) {
-        if let Some(p) = self.get_parent() {
+        // floating windows have no decoration
+        if self.get_parent().is_some() && !self.is_floating() {
             layout::decorate(self, focused);
         }
     }
 
+    pub fn update_decoration(&mut self, item: Decoration) {
+        if let Some(canvas) = self.canvas.as_mut() {
+            canvas.update(item);
+        }
+    }
+
+    // copy decorations of clients to the window, re-render only if changed
+    pub fn redraw(&mut self) {
+        let id = self.raw_id();
+        if let Some(canvas) = self.canvas.as_mut() {
+            canvas.render(id);
+        }
+    }
+
     // fullscreen & normal toggle
-    pub fn mode_toggle(&mut self) {
+    /// cover area, the output self is on, or go back into the parent
+    pub fn mode_toggle(&mut self, area: Rectangle) {
         let context = self.context;
         let id = self.raw_id();
         match self.mode {
             Mode::Normal => {
                 self.mode = Mode::Fullscreen;
                 println!("fullscreen {} {}", id, context.root);
-                libx::reparent(context, id, context.root, 0, 0);
-
-                let width = libx::display_width(context, context.screen_num);
-                let height = libx::display_height(context, context.screen_num);
-                libx::resize_window(context, id, 0, 0,
-                                    width,
-                                    height);
+                libx::reparent(context, id, context.root, area.x, area.y);
+
+                libx::resize_window(context, id, area.x, area.y,
+                                    area.width,
+                                    area.height);
+                libx::raise_window(context, id);
+                // a selected container lays its clients out full screen
+                if let Some(canvas) = self.canvas.as_mut() {
+                    canvas.resize(area.width, area.height);
+                }
+                self.update_layout();
             }
             Mode::Fullscreen => {
                 self.mode = Mode::Normal;
@@ -469,6 +668,66 @@ impl Container {
         }
     }
 
+    /// put client next to self, as the zone of a drop says: splitting
+    /// self if the parent is not laid out that way, or tabbing for
+    /// the center. Gives the client back if it can't be placed.
+    pub fn drop_client(&mut self, client: Container, zone: DropZone) -> Option<Container> {
+        let (direction, layout_type, after) = match zone {
+            DropZone::Left => (LayoutDirection::Horizontal, layout::Type::Tiling, false),
+            DropZone::Right => (LayoutDirection::Horizontal, layout::Type::Tiling, true),
+            DropZone::Top => (LayoutDirection::Vertical, layout::Type::Tiling, false),
+            DropZone::Bottom => (LayoutDirection::Vertical, layout::Type::Tiling, true),
+            DropZone::Center => (LayoutDirection::Horizontal, layout::Type::Tab, true),
+        };
+
+        let p = match self.get_parent() {
+            Some(p) => { p }
+            None => { return Some(client) }
+        };
+        let fits = p.layout == layout_type &&
+            (layout_type == layout::Type::Tab || p.direction == direction);
+        if fits {
+            // self moves when the parent inserts, don't touch it after
+            let index = p.contain(self.raw_id()).unwrap();
+            p.insert(if after { index+1 } else { index }, client);
+            p.update_layout();
+        }
+        else {
+            if !self.split() {
+                return Some(client)
+            }
+            self.layout = layout_type;
+            self.direction = direction;
+            if after {
+                self.add(client);
+            }
+            else {
+                self.insert(0, client);
+            }
+            self.update_layout();
+        }
+        None
+    }
+
+    /// the App window at a point in this container's coordinates
+    pub fn query_window(&self, x: i32, y: i32) -> Option<xlib::Window> {
+        if let Some(client) = self.query_point(x, y) {
+            if let Type::App = client.category {
+                return Some(client.raw_id())
+            }
+        }
+        for client in self.clients.iter() {
+            let rec = client.rec();
+            if rec.contain(x, y) {
+                return match client.category {
+                    Type::App => { Some(client.raw_id()) }
+                    _ => { client.query_window(x - rec.x, y - rec.y) }
+                }
+            }
+        }
+        None
+    }
+
     // decide which client when click on titlebar
     pub fn query_point(&self, x: i32, y: i32) -> Option<&Container>{
         for client in self.clients.iter() {
@@ -481,9 +740,9 @@ impl Container {
         None
     }
 
-    // decide if the point is on the border
+    // decide if the point is on the border between client i-1 and i
     pub fn query_border(&self, x: i32, y: i32) -> Option<usize> {
-        for (i, client) in self.clients.iter().enumerate() {
+        for (i, client) in self.clients.iter().enumerate().skip(1) {
             let rec = client.rec();
             match self.direction {
                 LayoutDirection::Vertical => {
@@ -501,6 +760,21 @@ impl Container {
         None
     }
 
+    /// like query_border, but also looks for borders of the ancestors
+    /// the point lies on, returns the container owning the border
+    pub fn find_border(&self, x: i32, y: i32) -> Option<(xlib::Window, usize)> {
+        if let Some(i) = self.query_border(x, y) {
+            return Some((self.raw_id(), i))
+        }
+        match self.get_parent() {
+            Some(p) => {
+                let rec = self.rec();
+                p.find_border(x + rec.x, y + rec.y)
+            }
+            None => { None }
+        }
+    }
+
     pub fn rec(&self) -> layout::Rectangle {
         let attrs = libx::get_window_attributes(self.context, self.raw_id());
         layout::Rectangle {
@@ -511,6 +785,92 @@ impl Container {
         }
     }
 
+    /// geometry of the window in root coordinates
+    pub fn root_rec(&self) -> layout::Rectangle {
+        let rec = self.rec();
+        let (x, y) = libx::root_position(self.context, self.raw_id());
+        layout::Rectangle {
+            x: x,
+            y: y,
+            width: rec.width,
+            height: rec.height
+        }
+    }
+
+    /// the leaf window in this subtree closest to a point in root coordinates
+    pub fn closest_leaf(&self, x: i32, y: i32) -> xlib::Window {
+        if self.is_empty() {
+            return self.raw_id()
+        }
+        let mut best = &self.clients[0];
+        // tabs all share one area, take the one focused last
+        if self.layout == layout::Type::Tab {
+            let last = self.focus_stack.iter()
+                .filter_map(|id| self.clients.iter().find(|c| c.raw_id() == *id))
+                .next();
+            if let Some(c) = last {
+                best = c;
+            }
+        }
+        else {
+            let mut best_distance = best.root_rec().distance(x, y);
+            for client in self.clients.iter().skip(1) {
+                let d = client.root_rec().distance(x, y);
+                if d < best_distance {
+                    best_distance = d;
+                    best = client;
+                }
+            }
+        }
+        best.closest_leaf(x, y)
+    }
+
+    /// the window to focus from self going in direction: the closest leaf
+    /// of the sibling of the nearest ancestor split along that direction,
+    /// or of the far side of the outermost one when wrapping
+    pub fn neighbor_leaf(&self, direction: MoveDirection, wrap: bool) -> Option<xlib::Window> {
+        let axis = direction.axis();
+        let forward = direction.forward();
+        let rec = self.root_rec();
+        let x = rec.x + rec.width as i32 / 2;
+        let y = rec.y + rec.height as i32 / 2;
+
+        let mut child: &Container = self;
+        let mut outer: Option<&Container> = None;
+        loop {
+            let ancestor = match child.get_parent() {
+                Some(a) => { a }
+                None => { break }
+            };
+            if ancestor.orientation() == axis && ancestor.size() > 1 {
+                // floating windows have no neighbors in the tree
+                let i = match ancestor.contain(child.raw_id()) {
+                    Some(i) => { i }
+                    None => { break }
+                };
+                if forward && i + 1 < ancestor.size() {
+                    return Some(ancestor.clients[i + 1].closest_leaf(x, y))
+                }
+                if !forward && i > 0 {
+                    return Some(ancestor.clients[i - 1].closest_leaf(x, y))
+                }
+                outer = Some(ancestor);
+            }
+            child = ancestor;
+        }
+
+        if !wrap {
+            return None
+        }
+        match outer {
+            Some(a) => {
+                let n = if forward { 0 } else { a.size() - 1 };
+                Some(a.clients[n].closest_leaf(x, y))
+            }
+            None => { None }
+        }
+    }
+
     pub fn circulate(&self, index: usize, direction: MoveDirection) -> Option<usize>{
         let size = self.size();
         if index >= size {
@@ -0,0 +1,172 @@
+extern crate x11;
+extern crate regex;
+
+use x11::xlib;
+use regex::Regex;
+use super::super::libx;
+use super::handler::HandleFn;
+
+/// the properties of a window that criteria look at
+pub struct WindowInfo {
+    pub class: Option<String>,
+    pub instance: Option<String>,
+    pub role: Option<String>,
+    pub title: Option<String>,
+    // _NET_WM_WINDOW_TYPE without prefix, lowercase: normal, dialog, ...
+    pub window_type: Vec<String>,
+}
+
+impl WindowInfo {
+    pub fn query(context: libx::Context, window: xlib::Window) -> WindowInfo {
+        let (instance, class) = match libx::get_class_hint(context, window) {
+            Some((instance, class)) => { (Some(instance), Some(class)) }
+            None => { (None, None) }
+        };
+        let title = match libx::get_text_property(context, window, libx::get_atom(context, "_NET_WM_NAME")) {
+            Some(s) => { Some(s) }
+            None => { libx::get_text_property(context, window, xlib::XA_WM_NAME) }
+        };
+        let role = libx::get_text_property(context, window, libx::get_atom(context, "WM_WINDOW_ROLE"));
+
+        let atom = libx::get_atom(context, "_NET_WM_WINDOW_TYPE");
+        let window_type = libx::get_atom_property(context, window, atom).iter()
+            .filter_map(|a| libx::get_atom_name(context, *a))
+            .map(|name| name.trim_left_matches("_NET_WM_WINDOW_TYPE_").to_lowercase())
+            .collect();
+
+        WindowInfo {
+            class: class,
+            instance: instance,
+            role: role,
+            title: title,
+            window_type: window_type,
+        }
+    }
+}
+
+/// `[class="Firefox" title="^mpv"]`: every given property has to match.
+/// Values are regular expressions, except window_type.
+pub struct Criteria {
+    class: Option<Regex>,
+    instance: Option<Regex>,
+    role: Option<Regex>,
+    title: Option<Regex>,
+    window_type: Option<String>,
+}
+
+impl Criteria {
+    /// parse criteria at the start of s, returning the rest of s
+    pub fn parse(s: &str) -> Option<(Criteria, &str)> {
+        let s = s.trim_left();
+        if !s.starts_with("[") {
+            return None
+        }
+        let mut criteria = Criteria {
+            class: None,
+            instance: None,
+            role: None,
+            title: None,
+            window_type: None,
+        };
+        let mut count = 0;
+        let mut chars = s.char_indices().skip(1).peekable();
+        loop {
+            // key
+            let mut key = String::new();
+            loop {
+                match chars.next() {
+                    Some((i, ']')) => {
+                        if !key.trim().is_empty() || count == 0 {
+                            return None
+                        }
+                        return Some((criteria, &s[i+1..]))
+                    }
+                    Some((_, '=')) => { break }
+                    Some((_, c)) => { key.push(c); }
+                    None => { return None }
+                }
+            }
+            // value, quoted or up to the next space
+            let mut value = String::new();
+            if let Some(&(_, '"')) = chars.peek() {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some((_, '\\')) => {
+                            if let Some((_, c)) = chars.next() {
+                                value.push(c);
+                            }
+                        }
+                        Some((_, '"')) => { break }
+                        Some((_, c)) => { value.push(c); }
+                        None => { return None }
+                    }
+                }
+            }
+            else {
+                loop {
+                    match chars.peek() {
+                        Some(&(_, ' ')) | Some(&(_, ']')) | None => { break }
+                        Some(&(_, c)) => {
+                            value.push(c);
+                            chars.next();
+                        }
+                    }
+                }
+            }
+
+            let regex = match key.trim() {
+                "window_type" => { None }
+                _ => {
+                    match Regex::new(&value) {
+                        Ok(r) => { Some(r) }
+                        Err(_) => { return None }
+                    }
+                }
+            };
+            match key.trim() {
+                "class" => { criteria.class = regex; }
+                "instance" => { criteria.instance = regex; }
+                "window_role" => { criteria.role = regex; }
+                "title" => { criteria.title = regex; }
+                "window_type" => { criteria.window_type = Some(value); }
+                _ => { return None }
+            }
+            count += 1;
+        }
+    }
+
+    pub fn matches(&self, info: &WindowInfo) -> bool {
+        fn check(regex: &Option<Regex>, value: &Option<String>) -> bool {
+            match (regex, value) {
+                (&None, _) => { true }
+                (&Some(ref r), &Some(ref v)) => { r.is_match(v) }
+                (&Some(_), &None) => { false }
+            }
+        }
+        let window_type = match self.window_type {
+            Some(ref t) => { info.window_type.contains(t) }
+            None => { true }
+        };
+        window_type &&
+            check(&self.class, &info.class) &&
+            check(&self.instance, &info.instance) &&
+            check(&self.role, &info.role) &&
+            check(&self.title, &info.title)
+    }
+}
+
+/// for_window: a command run on windows matching the criteria, once
+/// when they are mapped or once their title starts to match
+pub struct Rule {
+    pub criteria: Criteria,
+    pub handler: HandleFn,
+}
+
+/// assign: new windows matching the criteria open on a workspace
+pub struct Assign {
+    pub criteria: Criteria,
+    pub workspace: String,
+    // switch to the workspace when such a window opens
+    pub switch: bool,
+}
@@ -2,20 +2,47 @@ extern crate x11;
 extern crate libc;
 
 use std::ptr;
-use std::process::Command;
+use std::fmt;
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::boxed::Box;
+use std::collections::HashMap;
 
 use x11::xlib;
 use super::WindowManager;
 use super::Workspaces;
 use super::container;
 use super::layout::{self, LayoutDirection, MoveDirection};
+use super::criteria::{Criteria, WindowInfo};
+use super::startup::Launch;
+use super::command::OutputTarget;
 use super::super::libx::{self, Context};
 
-#[derive(Hash, Eq, PartialEq, Debug)]
+const MODIFIERS: [&'static str; 8] = ["$mod", "Shift", "Ctrl", "Mod1", "Mod2", "Mod3", "Mod4", "Mod5"];
+
+/// mask of a modifier name used in bindings
+pub fn modifier(mod_key: u32, name: &str) -> Option<u32> {
+    match name {
+        "$mod" => Some(mod_key),
+        "Shift" => Some(xlib::ShiftMask),
+        "Ctrl" => Some(xlib::ControlMask),
+        "Mod1" => Some(xlib::Mod1Mask),
+        "Mod2" => Some(xlib::Mod2Mask),
+        "Mod3" => Some(xlib::Mod3Mask),
+        "Mod4" => Some(xlib::Mod4Mask),
+        "Mod5" => Some(xlib::Mod5Mask),
+        _ => None
+    }
+}
+
+#[derive(Hash, Eq, PartialEq, Debug, Clone)]
 pub struct KeyBind {
     pub key: xlib::KeySym,
+    // bound by keycode instead of keysym if not 0
+    pub code: xlib::KeyCode,
     pub mask: u32,
+    // fire on KeyRelease instead of KeyPress
+    pub release: bool,
 }
 
 impl KeyBind {
@@ -23,16 +50,11 @@ impl KeyBind {
         let mut mask = 0;
         let mut sym = 0;
         for key in tokens {
-            match *key {
-                "$mod" => mask = mask | mod_key,
-                "Shift" => mask = mask | xlib::ShiftMask,
-                "Ctrl" => mask = mask | xlib::ControlMask,
-                "Mod1" => mask = mask | xlib::Mod1Mask,
-                "Mod2" => mask = mask | xlib::Mod2Mask,
-                "Mod3" => mask = mask | xlib::Mod3Mask,
-                "Mod4" => mask = mask | xlib::Mod4Mask,
-                "Mod5" => mask = mask | xlib::Mod5Mask,
-                _ => {
+            match modifier(mod_key, key) {
+                Some(m) => {
+                    mask = mask | m;
+                }
+                None => {
                     sym = libx::string_to_keysym(key);
                 }
             }
@@ -40,11 +62,180 @@ impl KeyBind {
 
         KeyBind {
             mask: mask,
-            key: sym
+            key: sym,
+            code: 0,
+            release: false,
+        }
+    }
+
+    /// parse one key combination like $mod+Shift+q,
+    /// None if it is not made of modifiers and exactly one valid key
+    pub fn parse(mod_key: u32, token: &str) -> Option<KeyBind> {
+        let keys: Vec<&str> = token.split("+").collect();
+        let count = keys.iter().filter(|k| !MODIFIERS.contains(k)).count();
+        if count != 1 {
+            return None
+        }
+        let bind = KeyBind::build(mod_key, &keys);
+        if bind.key == 0 {
+            None
+        }
+        else {
+            Some(bind)
+        }
+    }
+
+    /// parse a keycode combination like $mod+38 for bindcode
+    pub fn parse_code(mod_key: u32, token: &str) -> Option<KeyBind> {
+        let keys: Vec<&str> = token.split("+").collect();
+        let (mods, code) = keys.split_at(keys.len() - 1);
+        if !mods.iter().all(|m| MODIFIERS.contains(m)) {
+            return None
+        }
+        match code[0].parse::<xlib::KeyCode>() {
+            Ok(c) if c > 0 => {
+                let mut bind = KeyBind::build(mod_key, mods);
+                bind.code = c;
+                Some(bind)
+            }
+            _ => { None }
+        }
+    }
+}
+
+impl fmt::Display for KeyBind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let masks = [(xlib::ShiftMask, "Shift"),
+                     (xlib::ControlMask, "Ctrl"),
+                     (xlib::Mod1Mask, "Mod1"),
+                     (xlib::Mod2Mask, "Mod2"),
+                     (xlib::Mod3Mask, "Mod3"),
+                     (xlib::Mod4Mask, "Mod4"),
+                     (xlib::Mod5Mask, "Mod5")];
+        let mut s = String::new();
+        for &(mask, name) in masks.iter() {
+            if self.mask & mask != 0 {
+                s.push_str(name);
+                s.push('+');
+            }
+        }
+        if self.code != 0 {
+            s.push_str(&self.code.to_string());
         }
+        else {
+            match libx::keysym_to_string(self.key) {
+                Some(name) => { s.push_str(&name); }
+                None => { s.push_str("?"); }
+            }
+        }
+        write!(f, "{}", s)
     }
 }
 
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+pub enum MouseTarget {
+    Titlebar,
+    Window,
+    Root,
+    Border,
+}
+
+#[derive(Hash, Eq, PartialEq, Debug, Clone)]
+pub struct MouseBind {
+    pub button: u32,
+    pub mask: u32,
+    pub target: MouseTarget,
+}
+
+impl MouseBind {
+    /// parse a button combination like $mod+button4 and a target
+    pub fn parse(mod_key: u32, token: &str, target: &str) -> Option<MouseBind> {
+        let target = match target {
+            "titlebar" => MouseTarget::Titlebar,
+            "window" => MouseTarget::Window,
+            "root" => MouseTarget::Root,
+            "border" => MouseTarget::Border,
+            _ => { return None }
+        };
+
+        let mut mask = 0;
+        let mut button = 0;
+        for key in token.split("+") {
+            match modifier(mod_key, key) {
+                Some(m) => {
+                    mask = mask | m;
+                }
+                None => {
+                    if !key.starts_with("button") {
+                        return None
+                    }
+                    match key[6..].parse::<u32>() {
+                        Ok(b) if b > 0 => { button = b; }
+                        _ => { return None }
+                    }
+                }
+            }
+        }
+        if button == 0 {
+            return None
+        }
+
+        Some(MouseBind {
+            button: button,
+            mask: mask,
+            target: target,
+        })
+    }
+}
+
+/// a bound key either runs a handler or waits for the next key of a chord
+pub enum Binding {
+    Handler(HandleFn),
+    Prefix(HashMap<KeyBind, Binding>),
+}
+
+impl Binding {
+    /// bind keys to handler, returning whether that replaced bindings
+    /// of the same keys or of a prefix of them
+    pub fn insert(binds: &mut HashMap<KeyBind, Binding>, keys: &[KeyBind], handler: HandleFn) -> bool {
+        let (first, rest) = keys.split_at(1);
+        let key = first[0].clone();
+        if rest.is_empty() {
+            return binds.insert(key, Binding::Handler(handler)).is_some()
+        }
+
+        let entry = binds.entry(key).or_insert(Binding::Prefix(HashMap::new()));
+        let mut replaced = false;
+        if let Binding::Handler(_) = *entry {
+            *entry = Binding::Prefix(HashMap::new());
+            replaced = true;
+        }
+        if let Binding::Prefix(ref mut next) = *entry {
+            replaced |= Binding::insert(next, rest, handler);
+        }
+        replaced
+    }
+
+    pub fn lookup<'a>(binds: &'a mut HashMap<KeyBind, Binding>, keys: &[KeyBind]) -> Option<&'a mut Binding> {
+        let (first, rest) = keys.split_at(1);
+        match binds.get_mut(&first[0]) {
+            Some(binding) => {
+                if rest.is_empty() {
+                    return Some(binding)
+                }
+                match *binding {
+                    Binding::Prefix(ref mut next) => {
+                        Binding::lookup(next, rest)
+                    }
+                    Binding::Handler(_) => { None }
+                }
+            }
+            None => { None }
+        }
+    }
+}
+
+#[derive(PartialEq, Clone)]
 pub enum Resize {
     Shrink,
     Grow,
@@ -52,13 +243,79 @@ pub enum Resize {
 
 pub type HandleFn = Box<FnMut(&mut Workspaces)>;
 
-pub fn exec(mut cmd: Command) -> HandleFn {
-    Box::new(move |workspaces| {cmd.spawn();})
+// programs started so far, makes startup ids unique
+static LAUNCHES: AtomicUsize = AtomicUsize::new(0);
+
+/// start cmd running program, logging failures. Its first window opens
+/// on workspace, or on the current one. Children are reaped by the
+/// window manager on SIGCHLD.
+pub fn spawn(workspaces: &mut Workspaces, cmd: &mut Command, program: &str, workspace: Option<String>) {
+    let n = LAUNCHES.fetch_add(1, Ordering::SeqCst);
+    let name = program.rsplit('/').next().unwrap_or(program);
+    let id = format!("rustile/{}-{}-{}", name, process::id(), n);
+    cmd.env("DESKTOP_STARTUP_ID", &id);
+    match cmd.spawn() {
+        Ok(child) => {
+            let explicit = workspace.is_some();
+            let key = workspace.unwrap_or(workspaces.current_name());
+            workspaces.launches.push(Launch::new(id, Some(child.id()), key, explicit));
+        }
+        Err(err) => { error!("exec {:?}: {}", cmd, err); }
+    }
+}
+
+pub fn exec(mut cmd: Command, program: &str, workspace: Option<String>) -> HandleFn {
+    let program = program.to_string();
+    Box::new(move |workspaces| {
+        spawn(workspaces, &mut cmd, &program, workspace.clone());
+    })
+}
+
+pub fn switch_mode(name: String) -> HandleFn {
+    Box::new(move |workspaces| {
+        workspaces.set_bind_mode(&name);
+    })
+}
+
+// switch to the next or previous existing workspace
+pub fn cycle_workspace(forward: bool) -> HandleFn {
+    Box::new(move |workspaces| {
+        let keys = workspaces.names();
+        let current = workspaces.current_name();
+        if let Some(i) = keys.iter().position(|k| *k == current) {
+            let size = keys.len();
+            let next = if forward { (i+1) % size } else { (i+size-1) % size };
+            workspaces.switch_workspace(&keys[next]);
+        }
+    })
+}
+
+fn find_output(workspaces: &Workspaces, target: &OutputTarget) -> Option<usize> {
+    match *target {
+        OutputTarget::Direction(ref d) => { workspaces.output_towards(d.clone()) }
+        OutputTarget::Name(ref name) => { workspaces.output_named(name) }
+    }
+}
+
+pub fn focus_output(target: OutputTarget) -> HandleFn {
+    Box::new(move |workspaces| {
+        if let Some(i) = find_output(workspaces, &target) {
+            workspaces.focus_output(i);
+        }
+    })
 }
 
-pub fn switch_workspace(key: char) -> HandleFn {
+pub fn move_workspace_to_output(target: OutputTarget) -> HandleFn {
+    Box::new(move |workspaces| {
+        if let Some(i) = find_output(workspaces, &target) {
+            workspaces.move_workspace_to_output(i);
+        }
+    })
+}
+
+pub fn switch_workspace(key: String) -> HandleFn {
     let f = move |workspaces: &mut Workspaces| {
-        workspaces.switch_workspace(key);
+        workspaces.switch_workspace(&key);
     };
     Box::new(f)
 }
@@ -68,8 +325,9 @@ pub fn layout(layout: layout::Type) -> HandleFn {
         let old = workspaces.mode;
         workspaces.mode = container::Mode::Layout;
         let mut changed = false;
-        if let Some(container) = workspaces.get_focus() {
-            let c = if container.get_parent().is_some() {
+        if let Some(container) = workspaces.get_selected() {
+            // a window changes the layout of its parent, a selected container its own
+            let c = if container.is_app() && container.get_parent().is_some() {
                 container.get_parent().unwrap()
             }
             else {
@@ -88,49 +346,167 @@ pub fn layout(layout: layout::Type) -> HandleFn {
 
 pub fn fullscreen() -> HandleFn {
     Box::new(move |workspaces| {
-        if let Some(c) = workspaces.get_focus(){
-            c.mode_toggle();
+        let id = match workspaces.get_selected() {
+            Some(c) => { c.raw_id() }
+            None => { return }
+        };
+        // full screen is the output of the workspace
+        let area = match workspaces.get_container(id) {
+            Some((key, _)) => { workspaces.output_area(&key) }
+            None => { return }
+        };
+        if let Some(c) = workspaces.get_selected(){
+            c.mode_toggle(area);
         }
     })
 }
 
-pub fn move_window_to_workspace(key: char) -> HandleFn {
+/// float or tile the window, toggle it if floating is None
+pub fn floating(floating: Option<bool>) -> HandleFn {
     Box::new(move |workspaces| {
-        let id = match workspaces.get_focus() {
+        let (id, now) = match workspaces.get_selected() {
+            Some(c) => { (c.raw_id(), c.is_floating()) }
+            None => { return }
+        };
+        match floating.unwrap_or(!now) {
+            true => { workspaces.float_window(id); }
+            false => { workspaces.tile_window(id); }
+        }
+    })
+}
+
+// titlebar of the given height, 0 for none
+pub fn border(height: u32) -> HandleFn {
+    Box::new(move |workspaces| {
+        if let Some(c) = workspaces.get_selected() {
+            c.titlebar_height = height;
+            if let Some(p) = c.get_parent() {
+                p.update_layout();
+            }
+        }
+    })
+}
+
+pub fn move_window_to_workspace(key: String) -> HandleFn {
+    Box::new(move |workspaces| {
+        let id = match workspaces.get_selected() {
             Some(container) => {
                 container.raw_id()
             }
             None => { return }
         };
-        let from = workspaces.current_name();
-        let to = key;
-        workspaces.move_window(id, from, to);
+        // rules may move windows that are not on the current workspace
+        let from = match workspaces.get_container(id) {
+            Some((k, _)) => { k }
+            None => { return }
+        };
+        workspaces.move_window(id, &from, &key);
+    })
+}
+
+/// rename workspace old, or the current one
+pub fn rename_workspace(old: Option<String>, new: String) -> HandleFn {
+    Box::new(move |workspaces| {
+        let old = old.clone().unwrap_or(workspaces.current_name());
+        if !workspaces.rename(&old, &new) {
+            warn!("can't rename workspace {} to {}", old, new);
+        }
     })
 }
 
 pub fn focus_window(direction: MoveDirection) -> HandleFn {
     Box::new(move |workspaces| {
-        if let Some(c) = workspaces.get_focus() {
-            if let Some(p) = c.get_parent() {
-                let index = p.contain(c.raw_id()).unwrap();
+        let id = match workspaces.get_selected() {
+            Some(c) => { c.raw_id() }
+            None => { return }
+        };
+        workspaces.focus_towards(id, direction.clone());
+    })
+}
 
-                if let Some(next) = p.circulate(index, direction.clone()) {
-                    if next != index {
-                        c.unfocus();
-                        p.get_child(next).unwrap().focus();
-                    }
-                }
-            }
+pub fn reload() -> HandleFn {
+    Box::new(move |workspaces| {
+        workspaces.reload = true;
+    })
+}
+
+/// run handlers one after another
+pub fn chain(mut handlers: Vec<HandleFn>) -> HandleFn {
+    Box::new(move |workspaces| {
+        for handler in handlers.iter_mut() {
+            handler(workspaces);
         }
     })
 }
 
+/// run handler on every managed window matching criteria, instead of
+/// on the focused one
+pub fn with_criteria(criteria: Criteria, mut handler: HandleFn) -> HandleFn {
+    Box::new(move |workspaces| {
+        let context = workspaces.context;
+        let windows: Vec<xlib::Window> = workspaces.windows().into_iter()
+            .filter(|w| criteria.matches(&WindowInfo::query(context, *w)))
+            .collect();
+        let old = workspaces.target;
+        for w in windows {
+            workspaces.target = Some(w);
+            handler(workspaces);
+        }
+        workspaces.target = old;
+    })
+}
+
+// focus the window a rule or criteria points at
+pub fn focus_target() -> HandleFn {
+    Box::new(move |workspaces| {
+        if let Some(id) = workspaces.target {
+            workspaces.focus_anywhere(id);
+        }
+    })
+}
+
+pub fn focus_last() -> HandleFn {
+    Box::new(move |workspaces| {
+        workspaces.focus_last();
+    })
+}
+
+pub fn cycle_focus(forward: bool) -> HandleFn {
+    Box::new(move |workspaces| {
+        workspaces.cycle_focus(forward);
+    })
+}
+
+pub fn focus_parent() -> HandleFn {
+    Box::new(move |workspaces| {
+        workspaces.focus_parent();
+    })
+}
+
+pub fn focus_child() -> HandleFn {
+    Box::new(move |workspaces| {
+        workspaces.focus_child();
+    })
+}
+
+pub fn move_direction(direction: MoveDirection) -> HandleFn {
+    Box::new(move |workspaces| {
+        let id = match workspaces.get_selected() {
+            Some(c) => { c.raw_id() }
+            None => { return }
+        };
+        workspaces.move_direction(id, direction.clone());
+    })
+}
+
 pub fn close_window() -> HandleFn {
     Box::new(move |workspaces| {
         let context = workspaces.context;
-        if let Some(c) = workspaces.get_focus() {
-            libx::kill_window(context, c.raw_id());
-            println!("try kill window {}", c.raw_id());
+        if let Some(c) = workspaces.get_selected() {
+            for id in c.leaves() {
+                libx::kill_window(context, id);
+                println!("try kill window {}", id);
+            }
         }
     })
 }
@@ -138,7 +514,7 @@ pub fn close_window() -> HandleFn {
 pub fn split_container() -> HandleFn {
     Box::new(move |workspaces| {
         workspaces.current().print_tree(0);
-        if let Some(c) = workspaces.get_focus() {
+        if let Some(c) = workspaces.get_selected() {
             c.split();
         }
         workspaces.current().print_tree(0);
@@ -150,31 +526,33 @@ pub fn resize_window(direction: LayoutDirection, resize: Resize) -> HandleFn {
         let old = workspaces.mode;
         workspaces.mode = container::Mode::Layout;
         let mut changed = false;
-        if let Some(c) = workspaces.get_focus() {
+        if let Some(c) = workspaces.get_selected() {
             if let Some(p) = c.get_parent() {
-                let index = p.contain(c.raw_id()).unwrap();
-                if p.direction == direction {
-                    let step:f32 = match resize {
-                        Resize::Shrink => {
-                            -0.05
+                // floating windows are not part of the layout
+                if let Some(index) = p.contain(c.raw_id()) {
+                    if p.direction == direction {
+                        let step:f32 = match resize {
+                            Resize::Shrink => {
+                                -0.05
+                            }
+                            Resize::Grow => {
+                                0.05
+                            }
+                        };
+
+                        if index > 0 && index < (p.size() - 1) {
+                            p.resize_children(index, index-1, step);
+                            p.resize_children(index, index+1, step);
                         }
-                        Resize::Grow => {
-                            0.05
+                        else if index > 0 {
+                            p.resize_children(index, index-1, step*2.0);
                         }
-                    };
-
-                    if index > 0 && index < (p.size() - 1) {
-                        p.resize_children(index, index-1, step);
-                        p.resize_children(index, index+1, step);
-                    }
-                    else if index > 0 {
-                        p.resize_children(index, index-1, step*2.0);
-                    }
-                    else if index < (p.size() - 1) {
-                        p.resize_children(index, index+1, step*2.0);
+                        else if index < (p.size() - 1) {
+                            p.resize_children(index, index+1, step*2.0);
+                        }
+                        p.update_layout();
+                        changed = true;
                     }
-                    p.update_layout();
-                    changed = true;
                 }
             }
         }
@@ -7,6 +7,7 @@ use std::ptr;
 
 use super::super::libx;
 use super::container::{self, Container};
+use super::canvas::Decoration;
 
 
 const CWX: libc::c_uint = 1<<0;
@@ -19,7 +20,7 @@ const CWStackMode: libc::c_uint = 1<<6;
 
 const border: u32 = 1;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Rectangle {
     pub x: i32,
     pub y: i32,
@@ -28,6 +29,15 @@ pub struct Rectangle {
 }
 
 impl Rectangle {
+    /// squared distance from a point to the nearest point of the rectangle
+    pub fn distance(&self, x: i32, y: i32) -> i64 {
+        let right = self.x + self.width as i32;
+        let bottom = self.y + self.height as i32;
+        let dx = if x < self.x { self.x - x } else if x > right { x - right } else { 0 } as i64;
+        let dy = if y < self.y { self.y - y } else if y > bottom { y - bottom } else { 0 } as i64;
+        dx * dx + dy * dy
+    }
+
     pub fn contain(&self, x: i32, y: i32) -> bool{
         if x >= self.x && x <= (self.x + self.width as i32) && y >= self.y && y <= (self.y + self.height as i32) {
             true
@@ -51,80 +61,108 @@ pub enum MoveDirection {
     Right,
 }
 
-pub fn decorate(client: &Container, focused: bool) {
-    if let Some(rec) = client.titlebar {
-        set_titlebar(client, rec, focused);
-        set_title(client, rec, focused);
+impl MoveDirection {
+    /// the layout direction moving this way goes along
+    pub fn axis(&self) -> LayoutDirection {
+        match *self {
+            MoveDirection::Left | MoveDirection::Right => LayoutDirection::Horizontal,
+            MoveDirection::Up | MoveDirection::Down => LayoutDirection::Vertical,
+        }
     }
-    set_border(client, focused);
-}
-
-fn set_titlebar(client: &Container, rec: Rectangle, focused: bool) {
-    let mut context = client.context;
 
-    context.gc = if focused {
-        context.focus_gc
+    /// true if moving this way goes to a higher index among clients
+    pub fn forward(&self) -> bool {
+        match *self {
+            MoveDirection::Right | MoveDirection::Down => true,
+            MoveDirection::Left | MoveDirection::Up => false,
+        }
     }
-    else {
-        context.unfocus_gc
-    };
-
-    let pid = client.pid();
-    libx::fill_rectangle(context, pid,
-                         rec.x, rec.y,
-                         rec.width, rec.height);
 }
 
-fn set_title(client: &Container, rec: Rectangle, focused: bool) {
-    let mut context = client.context;
+/// where a dragged window is dropped on the target window
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum DropZone {
+    Left,
+    Right,
+    Top,
+    Bottom,
+    Center,
+}
 
-    context.gc = if focused {
-        context.focus_font_gc
+impl DropZone {
+    /// the zone of rec the point is in, edges take a quarter each side
+    pub fn from_point(rec: &Rectangle, x: i32, y: i32) -> DropZone {
+        if rec.width == 0 || rec.height == 0 {
+            return DropZone::Center
+        }
+        let fx = (x - rec.x) as f32 / rec.width as f32;
+        let fy = (y - rec.y) as f32 / rec.height as f32;
+        let edges = [(fx, DropZone::Left),
+                     (1.0 - fx, DropZone::Right),
+                     (fy, DropZone::Top),
+                     (1.0 - fy, DropZone::Bottom)];
+
+        let mut zone = DropZone::Center;
+        let mut nearest = 0.25;
+        for &(d, z) in edges.iter() {
+            if d < nearest {
+                nearest = d;
+                zone = z;
+            }
+        }
+        zone
     }
-    else {
-        context.unfocus_font_gc
-    };
-
-    let res = libx::get_text_property(context, client.raw_id(), xlib::XA_WM_NAME);
-
-    match res {
-        Some(s) => {
 
-            let (boundingbox, dummy) = libx::text_extents(context, s.clone());
-
-            let offset_x = -dummy.x as i32;
-            let offset_y = -dummy.y as i32;
-
-            let x = rec.x+offset_x;
-            let y = rec.y+offset_y;
-            let pid = client.pid();
-
-            libx::draw_string(context, s, pid, x, y);
+    /// the part of rec the dropped window will take
+    pub fn area(&self, rec: &Rectangle) -> Rectangle {
+        let half_w = rec.width / 2;
+        let half_h = rec.height / 2;
+        match *self {
+            DropZone::Left => {
+                Rectangle { x: rec.x, y: rec.y, width: half_w, height: rec.height }
+            }
+            DropZone::Right => {
+                Rectangle { x: rec.x + half_w as i32, y: rec.y, width: rec.width - half_w, height: rec.height }
+            }
+            DropZone::Top => {
+                Rectangle { x: rec.x, y: rec.y, width: rec.width, height: half_h }
+            }
+            DropZone::Bottom => {
+                Rectangle { x: rec.x, y: rec.y + half_h as i32, width: rec.width, height: rec.height - half_h }
+            }
+            DropZone::Center => { *rec }
         }
-        None =>{}
     }
 }
 
-fn set_border(client: &Container, focused: bool) {
-    let mut context = client.context;
-
-    context.gc = if focused {
-        context.focus_gc
-    }
-    else{
-        context.unfocus_gc
+pub fn decoration(client: &Container, rec: Rectangle, focused: bool) -> Decoration {
+    // border none leaves an empty titlebar
+    let titlebar = match client.titlebar {
+        Some(r) if r.height > 0 => { Some(r) }
+        _ => { None }
+    };
+    let title = match titlebar {
+        Some(_) => {
+            libx::get_text_property(client.context, client.raw_id(), xlib::XA_WM_NAME)
+        }
+        None => { None }
     };
-    unsafe {
-        xlib::XSetLineAttributes(context.display, context.gc, border, 0, 0, 0);
+    Decoration {
+        window: client.raw_id(),
+        titlebar: titlebar,
+        title: title,
+        rec: rec,
+        border: border,
+        focused: focused,
     }
-    let pid = client.pid();
-    let rec = client.rec();
+}
 
-    libx::draw_rectangle(context, pid,
-                         rec.x-border as i32,
-                         rec.y-border as i32,
-                         rec.width+border*2-1 as u32,
-                         rec.height+border*2-1 as u32);
+pub fn decorate(client: &Container, focused: bool) {
+    if let Some(p) = client.get_parent() {
+        let item = decoration(client, client.rec(), focused);
+        p.update_decoration(item);
+        p.redraw();
+    }
 }
 
 #[derive(PartialEq, Clone)]
@@ -134,6 +172,11 @@ pub enum Type {
 }
 
 pub fn update_layout(container: &mut Container) {
+    let ids: Vec<xlib::Window> = container.clients.iter().map(|c| c.raw_id()).collect();
+    if let Some(canvas) = container.canvas.as_mut() {
+        canvas.retain(&ids);
+    }
+
     match container.layout {
         Type::Tiling => {
             layout_tiling(container);
@@ -185,13 +228,20 @@ fn layout_tiling(container: &mut Container) {
         });
 
         let titlebar_height = client.titlebar.unwrap().height;
-        decorate(client, id==focus_id);;
-
-        client.configure(x+border as i32,
-                         y+titlebar_height as i32 + border as i32,
-                         w-border*2, h-titlebar_height-border*2);
+        let rec = Rectangle {
+            x: x+border as i32,
+            y: y+titlebar_height as i32 + border as i32,
+            width: w-border*2,
+            height: h-titlebar_height-border*2,
+        };
+        client.configure(rec.x, rec.y, rec.width, rec.height);
         // client.map();
+
+        if let Some(canvas) = container.canvas.as_mut() {
+            canvas.update(decoration(client, rec, id==focus_id || client.selected));
+        }
     }
+    container.redraw();
 }
 
 fn layout_tab(container: &mut Container) {
@@ -217,12 +267,17 @@ fn layout_tab(container: &mut Container) {
         });
 
         let titlebar_height = client.titlebar.unwrap().height;
-        decorate(client, id==focus_id);
+        let rec = Rectangle {
+            x: x_offset + border as i32,
+            y: y_offset+titlebar_height as i32 + border as i32,
+            width: attrs.width - border*2,
+            height: attrs.height -titlebar_height - border*2,
+        };
+        client.configure(rec.x, rec.y, rec.width, rec.height);
 
-        client.configure(x_offset + border as i32,
-                         y_offset+titlebar_height as i32 + border as i32,
-                         attrs.width - border*2 ,
-                         attrs.height -titlebar_height - border*2);
+        if let Some(canvas) = container.canvas.as_mut() {
+            canvas.update(decoration(client, rec, id==focus_id || client.selected));
+        }
         if focus_id == id {
             // client.map();
             libx::raise_window(client.context, id);
@@ -232,4 +287,41 @@ fn layout_tab(container: &mut Container) {
             // libx::lower_window(client.context, client.id);
         }
     }
+    container.redraw();
+}
+
+#[test]
+fn test_drop_zone() {
+    let rec = Rectangle { x: 10, y: 20, width: 100, height: 100 };
+    assert_eq!(DropZone::from_point(&rec, 15, 70), DropZone::Left);
+    assert_eq!(DropZone::from_point(&rec, 105, 70), DropZone::Right);
+    assert_eq!(DropZone::from_point(&rec, 60, 22), DropZone::Top);
+    assert_eq!(DropZone::from_point(&rec, 60, 118), DropZone::Bottom);
+    assert_eq!(DropZone::from_point(&rec, 60, 70), DropZone::Center);
+    // a quarter in is the center already, the nearest edge wins in corners
+    assert_eq!(DropZone::from_point(&rec, 34, 70), DropZone::Left);
+    assert_eq!(DropZone::from_point(&rec, 35, 70), DropZone::Center);
+    assert_eq!(DropZone::from_point(&rec, 12, 21), DropZone::Top);
+    let empty = Rectangle { x: 0, y: 0, width: 0, height: 10 };
+    assert_eq!(DropZone::from_point(&empty, 0, 0), DropZone::Center);
+}
+
+#[test]
+fn test_drop_area() {
+    let rec = Rectangle { x: 10, y: 20, width: 101, height: 50 };
+    assert_eq!(DropZone::Left.area(&rec), Rectangle { x: 10, y: 20, width: 50, height: 50 });
+    assert_eq!(DropZone::Right.area(&rec), Rectangle { x: 60, y: 20, width: 51, height: 50 });
+    assert_eq!(DropZone::Top.area(&rec), Rectangle { x: 10, y: 20, width: 101, height: 25 });
+    assert_eq!(DropZone::Bottom.area(&rec), Rectangle { x: 10, y: 45, width: 101, height: 25 });
+    assert_eq!(DropZone::Center.area(&rec), rec);
+}
+
+#[test]
+fn test_distance() {
+    let rec = Rectangle { x: 10, y: 20, width: 100, height: 50 };
+    assert_eq!(rec.distance(50, 40), 0);
+    assert_eq!(rec.distance(110, 70), 0);
+    assert_eq!(rec.distance(0, 30), 100);
+    assert_eq!(rec.distance(60, 10), 100);
+    assert_eq!(rec.distance(113, 74), 25);
 }
@@ -1,5 +1,5 @@
 pub use self::window_manager::WindowManager;
-pub use self::config::Config;
+pub use self::config::{Config, ConfigError};
 pub use self::workspaces::Workspaces;
 pub use self::container::Container;
 pub use self::taskbar::TaskBar;
@@ -8,6 +8,11 @@ mod window_manager;
 mod config;
 mod handler;
 mod layout;
+mod canvas;
+mod command;
+mod criteria;
 mod workspaces;
 mod container;
 mod taskbar;
+mod nagbar;
+mod startup;
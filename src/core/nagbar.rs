@@ -0,0 +1,84 @@
+extern crate x11;
+
+use x11::xlib;
+use std::mem;
+use super::super::libx;
+
+/// a bar across the top of the screen showing problems, like errors in
+/// the config, until it is clicked
+pub struct NagBar {
+    context: libx::Context,
+    id: xlib::Window,
+    line_height: u32,
+    lines: Vec<String>,
+}
+
+impl NagBar {
+    pub fn new(context: libx::Context, lines: Vec<String>) -> NagBar {
+        let line_height = 20;
+        let pid = context.root;
+        let attrs = libx::get_window_attributes(context, pid);
+        let height = line_height * lines.len() as u32;
+        let id = libx::create_window(context, pid, attrs.x, attrs.y,
+                                     attrs.width as u32,
+                                     height);
+
+        // attributes
+        let mut attrs: xlib::XSetWindowAttributes = unsafe { mem::zeroed() };
+        attrs.override_redirect = 1;
+        libx::set_window_attributes(context, id, xlib::CWOverrideRedirect, attrs);
+
+        // inputs
+        let mask = xlib::ButtonPressMask | xlib::ExposureMask;
+        libx::select_input(context, id, mask);
+        libx::map_window(context, id);
+        libx::raise_window(context, id);
+        NagBar {
+            context: context,
+            id: id,
+            line_height: line_height,
+            lines: lines,
+        }
+    }
+
+    pub fn update(&self) {
+        let mut context = self.context;
+        let attrs = libx::get_window_attributes(context, self.id);
+        context.gc = context.focus_gc;
+        libx::fill_rectangle(context, self.id, 0, 0,
+                             attrs.width as u32, attrs.height as u32);
+
+        context.gc = context.focus_font_gc;
+        for (i, line) in self.lines.iter().enumerate() {
+            let (_, dummy) = libx::text_extents(context, line.clone());
+            let y = (i as u32 * self.line_height) as i32;
+            let offset_y = (self.line_height as i32 - dummy.height as i32)/2 - dummy.y as i32;
+            libx::draw_string(context, line.clone(), self.id, 4 - dummy.x as i32, y + offset_y);
+        }
+    }
+
+    /// true once the bar was clicked away
+    pub fn handle(&mut self, e: &xlib::XEvent) -> bool {
+        let t = e.get_type();
+        match t {
+            xlib::Expose => {
+                let event: xlib::XExposeEvent = From::from(*e);
+                if event.window == self.id {
+                    self.update();
+                }
+                false
+            }
+            xlib::ButtonPress => {
+                let event: xlib::XButtonEvent = From::from(*e);
+                event.window == self.id
+            }
+            _ => { false }
+        }
+    }
+}
+
+impl Drop for NagBar {
+    fn drop(&mut self) {
+        libx::destroy_window(self.context, self.id);
+    }
+}
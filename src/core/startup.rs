@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// seconds a launch waits for its window before it is forgotten
+pub const TIMEOUT: u64 = 30;
+
+/// a program started by us or announced by a _NET_STARTUP_INFO new:
+/// message, whose first window opens on the workspace it was launched from
+pub struct Launch {
+    // DESKTOP_STARTUP_ID given to the program
+    pub id: String,
+    pub pid: Option<u32>,
+    pub workspace: String,
+    // asked for with exec --workspace, wins over assign
+    pub explicit: bool,
+    // a startup-aware client announced the id with new: or change:
+    pub claimed: bool,
+    pub started: Instant,
+}
+
+impl Launch {
+    pub fn new(id: String, pid: Option<u32>, workspace: String, explicit: bool) -> Launch {
+        Launch {
+            id: id,
+            pid: pid,
+            workspace: workspace,
+            explicit: explicit,
+            claimed: false,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn deadline(&self) -> Instant {
+        self.started + Duration::from_secs(TIMEOUT)
+    }
+}
+
+/// drop the launch of pid once it exited, unless its client claimed the
+/// startup id: that one waits for its window until remove: or the timeout
+pub fn child_exited(launches: &mut Vec<Launch>, pid: u32) {
+    launches.retain(|l| l.pid != Some(pid) || l.claimed);
+    for l in launches.iter_mut() {
+        if l.pid == Some(pid) {
+            l.pid = None;
+        }
+    }
+}
+
+/// append the 20 byte chunk of a _NET_STARTUP_INFO message to buf,
+/// giving the whole message once its terminating nul arrived
+pub fn push_chunk(buf: &mut Vec<u8>, chunk: &[u8]) -> Option<String> {
+    for &b in chunk {
+        if b == 0 {
+            let message = String::from_utf8_lossy(buf).into_owned();
+            buf.clear();
+            return Some(message)
+        }
+        buf.push(b);
+    }
+    None
+}
+
+/// parse a startup notification message like `remove: ID="a b" PID=3`
+/// into its type and keys
+pub fn parse_message(s: &str) -> Option<(String, HashMap<String, String>)> {
+    let colon = match s.find(':') {
+        Some(i) => { i }
+        None => { return None }
+    };
+    let kind = s[..colon].to_string();
+    let mut keys = HashMap::new();
+    let mut chars = s[colon+1..].chars().peekable();
+    loop {
+        while let Some(&' ') = chars.peek() {
+            chars.next();
+        }
+        let mut key = String::new();
+        loop {
+            match chars.next() {
+                Some('=') => { break }
+                Some(c) => { key.push(c); }
+                None => {
+                    if !key.is_empty() {
+                        return None
+                    }
+                    return Some((kind, keys))
+                }
+            }
+        }
+        let mut value = String::new();
+        let mut in_quote = false;
+        loop {
+            match chars.next() {
+                Some('\\') => {
+                    if let Some(c) = chars.next() {
+                        value.push(c);
+                    }
+                }
+                Some('"') => { in_quote = !in_quote; }
+                Some(' ') if !in_quote => { break }
+                Some(c) => { value.push(c); }
+                None => { break }
+            }
+        }
+        keys.insert(key, value);
+    }
+}
+
+#[test]
+fn test_parse_message() {
+    let (kind, keys) = parse_message("new: ID=a/b-1 PID=3 NAME=\"two words\"").unwrap();
+    assert_eq!(kind, "new");
+    assert_eq!(keys["ID"], "a/b-1");
+    assert_eq!(keys["PID"], "3");
+    assert_eq!(keys["NAME"], "two words");
+
+    let (kind, keys) = parse_message("remove: ID=\"a \\\"b\\\" c\\\\d\"").unwrap();
+    assert_eq!(kind, "remove");
+    assert_eq!(keys["ID"], "a \"b\" c\\d");
+
+    let (_, keys) = parse_message("remove:").unwrap();
+    assert!(keys.is_empty());
+    assert!(parse_message("new ID=1").is_none());
+    assert!(parse_message("new: ID").is_none());
+}
+
+#[test]
+fn test_push_chunk() {
+    let message = b"new: ID=\"a b\" NAME=xterm SCREEN=0";
+    let mut buf = Vec::new();
+    let mut result = None;
+    for chunk in message.chunks(20) {
+        let mut data = [0u8; 20];
+        data[..chunk.len()].copy_from_slice(chunk);
+        assert!(result.is_none());
+        result = push_chunk(&mut buf, &data);
+    }
+    // the message filled its last chunk, the nul comes in another
+    if result.is_none() {
+        result = push_chunk(&mut buf, &[0u8; 20]);
+    }
+    let result = result.unwrap();
+    assert_eq!(result, "new: ID=\"a b\" NAME=xterm SCREEN=0");
+    assert!(buf.is_empty());
+    let (_, keys) = parse_message(&result).unwrap();
+    assert_eq!(keys["ID"], "a b");
+    assert_eq!(keys["SCREEN"], "0");
+}
+
+#[test]
+fn test_child_exited() {
+    let mut launches = vec![
+        Launch::new("a".to_string(), Some(10), "1".to_string(), false),
+        Launch::new("b".to_string(), Some(11), "1".to_string(), false),
+        Launch::new("c".to_string(), Some(12), "2".to_string(), true),
+    ];
+    launches[0].claimed = true;
+    child_exited(&mut launches, 10);
+    child_exited(&mut launches, 11);
+    assert_eq!(launches.len(), 2);
+    assert_eq!(launches[0].id, "a");
+    assert_eq!(launches[0].pid, None);
+    assert_eq!(launches[1].pid, Some(12));
+}
@@ -2,27 +2,23 @@ extern crate x11;
 
 use x11::xlib;
 use std::mem;
+use std::cmp;
 use super::super::libx;
 pub struct TaskBar {
     context: libx::Context,
     id: xlib::Window,
     height: u32,
-    current: Option<char>,
-    workspaces: Vec<char>,
+    current: Option<String>,
+    workspaces: Vec<String>,
+    mode: Option<String>,
+    hint: Option<String>,
 }
 
 impl TaskBar {
-    pub fn new(context: libx::Context, height: u32, position: i32) -> TaskBar {
+    /// a bar at the top of an output
+    pub fn new(context: libx::Context, x: i32, y: i32, width: u32, height: u32) -> TaskBar {
         let pid = context.root;
-        let attrs = libx::get_window_attributes(context, pid);
-        let y: i32 = if position > 0 {
-            attrs.y
-        }else{
-            attrs.y + attrs.height - height as i32
-        };
-        let id = libx::create_window(context, pid, attrs.x, y,
-                                     attrs.width as u32,
-                                     height);
+        let id = libx::create_window(context, pid, x, y, width, height);
 
         // attributes
         let mut attrs: xlib::XSetWindowAttributes = unsafe { mem::zeroed() };
@@ -38,29 +34,47 @@ impl TaskBar {
             id: id,
             height: height,
             current: None,
-            workspaces: Vec::new()
+            workspaces: Vec::new(),
+            mode: None,
+            hint: None,
         }
     }
 
-    pub fn load(&mut self, keys: Vec<char>) {
+    /// move the bar, like when its output changed
+    pub fn configure(&mut self, x: i32, y: i32, width: u32) {
+        libx::resize_window(self.context, self.id, x, y, width, self.height);
+        self.update();
+    }
+
+    pub fn load(&mut self, keys: Vec<String>) {
         self.workspaces = keys;
     }
 
-    pub fn set_current(&mut self, current: char) {
+    pub fn set_current(&mut self, current: String) {
         self.current = Some(current);
     }
 
+    pub fn set_mode(&mut self, mode: Option<String>) {
+        self.mode = mode;
+    }
+
+    pub fn set_hint(&mut self, hint: Option<String>) {
+        self.hint = hint;
+    }
+
     pub fn update(&mut self) {
         let mut context = self.context;
         let gc = context.gc;
         let display = context.display;
-        for (i, v) in self.workspaces.iter().enumerate() {
-            let x = (i as u32 * (self.height + 1)) as i32 + 1;
+        // boxes are square, or as wide as the name needs
+        let mut x = 1;
+        for v in self.workspaces.iter() {
             let y = 1;
-            let width = self.height - 2;
             let height = self.height - 2;
+            let (_, extents) = libx::text_extents(context, v.clone());
+            let width = cmp::max(self.height - 2, extents.width as u32 + 4);
             unsafe{
-                let is_current = self.current.is_some() && v.clone() == self.current.unwrap();
+                let is_current = self.current.as_ref() == Some(v);
                 context.gc = if is_current {
                     context.focus_gc
                 }
@@ -87,9 +101,49 @@ impl TaskBar {
                 libx::draw_string(context, s, self.id,
                                   x+offset_x, y+offset_y);
             }
+            x += width as i32 + 1;
+        }
+        self.draw_status(x);
+    }
+
+    // show the active binding mode and pending chord right after the workspaces
+    fn draw_status(&self, x: i32) {
+        let attrs = libx::get_window_attributes(self.context, self.id);
+
+        // clear the old status
+        unsafe {
+            xlib::XClearArea(self.context.display, self.id,
+                             x, 0, attrs.width as u32, self.height, 0);
+        }
+
+        let mut x = x;
+        if let Some(ref name) = self.mode {
+            x = self.draw_label(name.clone(), x, true);
+        }
+        if let Some(ref hint) = self.hint {
+            self.draw_label(hint.clone(), x, false);
         }
     }
 
+    // draw text in a box starting at x, return where the next box starts
+    fn draw_label(&self, s: String, x: i32, focused: bool) -> i32 {
+        let mut context = self.context;
+        let y = 1;
+        let height = self.height - 2;
+        let (_, dummy) = libx::text_extents(context, s.clone());
+        let width = dummy.width as u32 + 4;
+
+        context.gc = if focused { context.focus_gc } else { context.unfocus_gc };
+        libx::fill_rectangle(context, self.id, x, y, width, height);
+
+        context.gc = if focused { context.focus_font_gc } else { context.unfocus_font_gc };
+        let offset_x = 2 - dummy.x as i32;
+        let offset_y = (height as i32 - dummy.height as i32)/2 - dummy.y as i32;
+        libx::draw_string(context, s, self.id, x+offset_x, y+offset_y);
+
+        x + width as i32 + 1
+    }
+
     pub fn handle(&mut self, e: &xlib::XEvent) {
         let t = e.get_type();
         match t {
@@ -103,3 +157,9 @@ impl TaskBar {
         }
     }
 }
+
+impl Drop for TaskBar {
+    fn drop(&mut self) {
+        libx::destroy_window(self.context, self.id);
+    }
+}
@@ -2,16 +2,49 @@ extern crate libc;
 extern crate x11;
 
 use std::ptr;
+use std::mem;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
 use x11::xlib;
+use x11::keysym;
+use x11::xrandr;
 use x11::xlib::Window;
 use super::super::libx;
 
-use super::config::Config;
+use super::config::{self, Config};
 use super::container::{self, Container};
 use super::layout;
 use super::Workspaces;
-use super::TaskBar;
+use super::nagbar::NagBar;
 use super::handler;
+use super::criteria::WindowInfo;
+use super::startup::{self, Launch};
+
+// cursor font shapes
+const LEFT_PTR: u32 = 68;
+const WATCH: u32 = 150;
+const SB_H_DOUBLE_ARROW: u32 = 108;
+const SB_V_DOUBLE_ARROW: u32 = 116;
+
+// milliseconds between layout updates while dragging a border
+const MOTION_INTERVAL: xlib::Time = 16;
+
+// pixels the pointer has to move before a titlebar press becomes a drag
+const DRAG_THRESHOLD: i32 = 8;
+
+// modifier bits of an event state, without buttons
+const MODIFIER_MASK: u32 = xlib::ShiftMask | xlib::ControlMask | xlib::Mod1Mask | xlib::Mod2Mask | xlib::Mod3Mask | xlib::Mod4Mask | xlib::Mod5Mask;
+
+// a window being dragged by its titlebar
+struct Drag {
+    window: Window,
+    x: i32,
+    y: i32,
+    active: bool,
+    target: Option<(Window, layout::DropZone)>,
+}
 
 unsafe extern fn error_handler(display: *mut xlib::Display, event: *mut xlib::XErrorEvent) -> libc::c_int {
     // match event.error_code {
@@ -24,6 +57,62 @@ unsafe extern fn error_handler(display: *mut xlib::Display, event: *mut xlib::XE
     1
 }
 
+// write end of a pipe the signal handlers write to, so that the event
+// loop wakes up even if the signal came just before it started waiting
+static WAKE_FD: AtomicI32 = AtomicI32::new(-1);
+
+fn wake() {
+    let fd = WAKE_FD.load(Ordering::SeqCst);
+    if fd >= 0 {
+        unsafe {
+            libc::write(fd, b"x".as_ptr() as *const libc::c_void, 1);
+        }
+    }
+}
+
+// set by SIGHUP, the config is reloaded in the event loop
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+extern fn sighup_handler(_: libc::c_int) {
+    RELOAD.store(true, Ordering::SeqCst);
+    wake();
+}
+
+// set by SIGCHLD, exited children are reaped in the event loop
+static CHILD_EXITED: AtomicBool = AtomicBool::new(false);
+
+extern fn sigchld_handler(_: libc::c_int) {
+    CHILD_EXITED.store(true, Ordering::SeqCst);
+    wake();
+}
+
+// a non-blocking pipe not inherited by children, gives its read end
+// and sets WAKE_FD to its write end
+fn wake_pipe() -> libc::c_int {
+    let mut fds: [libc::c_int; 2] = [-1, -1];
+    unsafe {
+        if libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) != 0 {
+            panic!("can't create pipe");
+        }
+    }
+    WAKE_FD.store(fds[1], Ordering::SeqCst);
+    fds[0]
+}
+
+// wait for every exited child, so none is left a zombie, giving their pids
+fn reap_children() -> Vec<u32> {
+    let mut pids = Vec::new();
+    loop {
+        let pid = unsafe { libc::waitpid(-1, ptr::null_mut(), libc::WNOHANG) };
+        if pid <= 0 {
+            break
+        }
+        debug!("child {} exited", pid);
+        pids.push(pid as u32);
+    }
+    pids
+}
+
 extern "C" {
     fn setlocale(category: i32, locale: *const i8) -> *mut i8;
 }
@@ -40,6 +129,7 @@ fn load_resource(mut context: &mut libx::Context) {
     let unfocus_gc = libx::create_gc(*context, context.root, 0, values);
     let focus_font_gc = libx::create_gc(*context, context.root, 0, values);
     let unfocus_font_gc = libx::create_gc(*context, context.root, 0, values);
+    let background_gc = libx::create_gc(*context, context.root, 0, values);
     unsafe {
         let black = xlib::XBlackPixel(display, screen);
         let white = xlib::XWhitePixel(display, screen);
@@ -59,6 +149,9 @@ fn load_resource(mut context: &mut libx::Context) {
         xlib::XSetBackground(display, unfocus_font_gc, gray.pixel);
         xlib::XSetForeground(display, unfocus_font_gc, white);
 
+        xlib::XSetForeground(display, background_gc, black);
+        xlib::XSetGraphicsExposures(display, background_gc, 0);
+
         // load fontset
         let s = ffi::CString::new("").unwrap().as_ptr();
         let p = setlocale(6, s);
@@ -85,13 +178,45 @@ fn load_resource(mut context: &mut libx::Context) {
     context.unfocus_gc = unfocus_gc;
     context.focus_font_gc = focus_font_gc;
     context.unfocus_font_gc = unfocus_font_gc;
+    context.background_gc = background_gc;
 
 }
 
 pub struct WindowManager {
     pub context: libx::Context,
     pub workspaces: Workspaces,
-    config: Config
+    config: Config,
+    // config file given on the command line, searched for if None
+    pub config_path: Option<PathBuf>,
+    // keys of a chord typed so far
+    chord: Vec<handler::KeyBind>,
+    chord_deadline: Option<Instant>,
+    keyboard_grabbed: bool,
+    // CapsLock, NumLock and ScrollLock bits, ignored when matching keys
+    lock_mask: u32,
+    // every combination of the lock bits, used when grabbing
+    locks: Vec<u32>,
+    // container whose border is being dragged
+    resizing: Option<Window>,
+    last_motion: xlib::Time,
+    cursors: HashMap<u32, xlib::Cursor>,
+    // container window and cursor shape set while hovering a border
+    hover: Option<(Window, u32)>,
+    drag: Option<Drag>,
+    // shows where a dragged window will be dropped
+    overlay: Window,
+    // indices of the for_window rules already run on a window
+    applied: HashMap<Window, Vec<usize>>,
+    // config errors shown at startup
+    nagbar: Option<NagBar>,
+    // startup notification messages being received, by sender window
+    startup_info: HashMap<Window, Vec<u8>>,
+    // the busy cursor is shown
+    busy: bool,
+    // first RandR event number, None without RandR
+    randr_event: Option<i32>,
+    // read end of the pipe signal handlers wake the event loop with
+    wake: libc::c_int,
 }
 
 impl WindowManager {
@@ -109,11 +234,33 @@ impl WindowManager {
 	context.screen_num  = libx::default_screen(context);
 	context.root = libx::root_window(context, context.screen_num);
         load_resource(&mut context);
+        let randr_event = libx::randr_event_base(context);
+        if randr_event.is_some() {
+            libx::randr_select_input(context);
+        }
 
 	let mut wm = WindowManager {
             context: context,
             config: Config::new(),
-            workspaces: Workspaces::new(context)
+            workspaces: Workspaces::new(context),
+            chord: Vec::new(),
+            chord_deadline: None,
+            keyboard_grabbed: false,
+            lock_mask: xlib::LockMask,
+            locks: vec![0],
+            resizing: None,
+            last_motion: 0,
+            cursors: HashMap::new(),
+            hover: None,
+            drag: None,
+            overlay: 0,
+            applied: HashMap::new(),
+            nagbar: None,
+            startup_info: HashMap::new(),
+            busy: false,
+            randr_event: randr_event,
+            wake: -1,
+            config_path: None,
         };
         wm
     }
@@ -148,19 +295,21 @@ impl WindowManager {
     }
 
     pub fn handle_destroy(&mut self, event: &xlib::XDestroyWindowEvent) {
+        let ancestors = self.workspaces.ancestors(event.window);
         self.workspaces.remove_window(event.window);
+        self.applied.remove(&event.window);
         if self.workspaces.get_focus().is_none() {
-            let id = self.workspaces.current().raw_id();
-            self.workspaces.set_focus(id);
+            self.workspaces.restore_focus(&ancestors);
         }
     }
 
     pub fn handle_expose(&mut self, event: &xlib::XExposeEvent) {
+        if event.count > 0 {
+            return
+        }
         let res = self.workspaces.get_container(event.window);
         if let Some((_, c)) = res {
-            for client in c.clients.iter() {
-                client.decorate(client.is_focused());
-            }
+            c.redraw();
         }
     }
     pub fn handle_map_request(&mut self, event: &xlib::XMapRequestEvent) {
@@ -181,14 +330,164 @@ impl WindowManager {
             if self.config.titlebar_height > 0 {
                 container.titlebar_height = self.config.titlebar_height;
             }
-            self.workspaces.insert_window(container);
+            self.grab_buttons(container.raw_id(), handler::MouseTarget::Window);
+            let id = container.raw_id();
+            // exec --workspace wins over assign, which wins over the
+            // workspace a program was launched from
+            let launch = self.take_launch(id);
+            let assign = match launch {
+                Some(ref l) if l.explicit => { None }
+                _ if self.config.assigns.is_empty() => { None }
+                _ => {
+                    let info = WindowInfo::query(self.context, id);
+                    self.config.assigns.iter()
+                        .find(|a| a.criteria.matches(&info))
+                        .map(|a| (a.workspace.clone(), a.switch))
+                }
+            };
+            let assign = assign.or(launch.map(|l| (l.workspace, false)));
+            match assign {
+                Some((key, switch)) if key != self.workspaces.current_name() => {
+                    if !self.workspaces.contain(&key) {
+                        self.workspaces.create(&key);
+                    }
+                    self.workspaces.add_window(container, Some(&key));
+                    if switch {
+                        self.workspaces.switch_workspace(&key);
+                        self.workspaces.set_focus(id);
+                    }
+                }
+                _ => {
+                    self.workspaces.insert_window(container);
+                }
+            }
+            self.apply_rules(id);
         }
         else {
             libx::map_window(self.context, event.window);
         }
     }
 
+    // the launch that started the program of window, found by the
+    // _NET_STARTUP_ID of the window or its client leader, else by
+    // _NET_WM_PID. Only the first window of a launch is placed.
+    fn take_launch(&mut self, window: Window) -> Option<Launch> {
+        if self.workspaces.launches.is_empty() {
+            return None
+        }
+        let startup_id = libx::get_atom(self.context, "_NET_STARTUP_ID");
+        let leader = libx::get_atom(self.context, "WM_CLIENT_LEADER");
+        let id = match libx::get_text_property(self.context, window, startup_id) {
+            Some(id) => { Some(id) }
+            None => {
+                libx::get_window_id_property(self.context, window, leader)
+                    .and_then(|l| libx::get_text_property(self.context, l, startup_id))
+            }
+        };
+        let atom = libx::get_atom(self.context, "_NET_WM_PID");
+        let pid = libx::get_cardinal_property(self.context, window, atom);
+
+        let found = self.workspaces.launches.iter().position(|l| {
+            match id {
+                Some(ref id) => { l.id == *id }
+                None => { pid.is_some() && l.pid == pid }
+            }
+        });
+        found.map(|i| self.workspaces.launches.remove(i))
+    }
+
+    // the message of a _NET_STARTUP_INFO_BEGIN and _NET_STARTUP_INFO
+    // sequence from some window, once its terminating nul arrived
+    fn startup_message(&mut self, event: &xlib::XClientMessageEvent) -> Option<String> {
+        let begin = libx::get_atom(self.context, "_NET_STARTUP_INFO_BEGIN");
+        let mut chunk = [0u8; 20];
+        for i in 0..20 {
+            chunk[i] = event.data.get_byte(i) as u8;
+        }
+        let message = {
+            let buf = self.startup_info.entry(event.window).or_insert(Vec::new());
+            if event.message_type == begin {
+                buf.clear();
+            }
+            startup::push_chunk(buf, &chunk)
+        };
+        if message.is_some() {
+            self.startup_info.remove(&event.window);
+        }
+        message
+    }
+
+    // a launcher announced a program, or the program finished starting
+    fn handle_startup(&mut self, message: &str) {
+        let (kind, keys) = match startup::parse_message(message) {
+            Some(m) => { m }
+            None => { return }
+        };
+        let id = match keys.get("ID") {
+            Some(id) => { id.clone() }
+            None => { return }
+        };
+        match kind.as_str() {
+            "new" | "change" => {
+                // the client knows its startup id, it will send remove:
+                if let Some(l) = self.workspaces.launches.iter_mut().find(|l| l.id == id) {
+                    l.claimed = true;
+                    return
+                }
+                if kind != "new" {
+                    return
+                }
+                let pid = keys.get("PID").and_then(|p| p.parse().ok());
+                let key = self.workspaces.current_name();
+                let mut launch = Launch::new(id, pid, key, false);
+                launch.claimed = true;
+                self.workspaces.launches.push(launch);
+            }
+            "remove" => {
+                self.workspaces.launches.retain(|l| l.id != id);
+            }
+            _ => {}
+        }
+    }
+
+    // show a busy cursor on the root while launches are pending
+    fn update_busy_cursor(&mut self) {
+        let busy = !self.workspaces.launches.is_empty();
+        if busy != self.busy {
+            self.busy = busy;
+            let root = self.context.root;
+            self.set_cursor(root, if busy { WATCH } else { LEFT_PTR });
+        }
+    }
+
+    // run the for_window rules matching window that didn't run on it yet
+    fn apply_rules(&mut self, window: Window) {
+        if self.config.rules.is_empty() {
+            return
+        }
+        let info = WindowInfo::query(self.context, window);
+        let applied = self.applied.entry(window).or_insert(Vec::new());
+        for (i, rule) in self.config.rules.iter_mut().enumerate() {
+            if applied.contains(&i) || !rule.criteria.matches(&info) {
+                continue
+            }
+            applied.push(i);
+            self.workspaces.target = Some(window);
+            (rule.handler)(&mut self.workspaces);
+            self.workspaces.target = None;
+        }
+    }
+
     pub fn handle_client_message(&mut self, event: &xlib::XClientMessageEvent) {
+        let begin = libx::get_atom(self.context, "_NET_STARTUP_INFO_BEGIN");
+        let info = libx::get_atom(self.context, "_NET_STARTUP_INFO");
+        if event.message_type == begin || event.message_type == info {
+            if let Some(message) = self.startup_message(event) {
+                self.handle_startup(&message);
+            }
+            return
+        }
+
         println!("message type {}", event.message_type);
         let s = libx::get_atom_name(self.context, event.message_type);
 
@@ -220,93 +519,454 @@ impl WindowManager {
     }
 
     pub fn handle_button_motion(&mut self, event: &xlib::XMotionEvent) {
+        if let Some(id) = self.resizing {
+            if event.time.wrapping_sub(self.last_motion) < MOTION_INTERVAL {
+                return
+            }
+            self.last_motion = event.time;
+            self.resize_border(id, event.x_root, event.y_root);
+        }
+        else if self.drag.is_some() {
+            if event.time.wrapping_sub(self.last_motion) < MOTION_INTERVAL {
+                return
+            }
+            self.last_motion = event.time;
+            self.update_drag(event.x_root, event.y_root);
+        }
+        else {
+            // finding the border asks the server for every client
+            if event.time.wrapping_sub(self.last_motion) < MOTION_INTERVAL {
+                return
+            }
+            self.last_motion = event.time;
+            self.update_hover(event);
+        }
+    }
+
+    // find the drop target under the pointer and show its zone
+    fn update_drag(&mut self, x_root: i32, y_root: i32) {
+        let window = match self.drag {
+            Some(ref mut d) => {
+                if !d.active && (x_root - d.x).abs() + (y_root - d.y).abs() < DRAG_THRESHOLD {
+                    return
+                }
+                d.active = true;
+                d.window
+            }
+            None => { return }
+        };
+
+        let target = match self.workspaces.query_window(x_root, y_root) {
+            Some((id, rec)) => {
+                if id == window {
+                    None
+                }
+                else {
+                    let zone = layout::DropZone::from_point(&rec, x_root, y_root);
+                    Some((id, zone, zone.area(&rec)))
+                }
+            }
+            None => { None }
+        };
 
+        match target {
+            Some((id, zone, area)) => {
+                libx::resize_window(self.context, self.overlay,
+                                    area.x, area.y, area.width, area.height);
+                libx::map_window(self.context, self.overlay);
+                libx::raise_window(self.context, self.overlay);
+                if let Some(ref mut d) = self.drag {
+                    d.target = Some((id, zone));
+                }
+            }
+            None => {
+                libx::unmap_window(self.context, self.overlay);
+                if let Some(ref mut d) = self.drag {
+                    d.target = None;
+                }
+            }
+        }
+    }
+
+    fn create_overlay(&mut self) {
+        let context = self.context;
+        let id = libx::create_window(context, context.root, 0, 0, 1, 1);
+        let mut attrs: xlib::XSetWindowAttributes = unsafe { mem::zeroed() };
+        attrs.override_redirect = 1;
+        attrs.background_pixel = libx::alloc_color(context, "blue").pixel;
+        libx::set_window_attributes(context, id,
+                                    xlib::CWOverrideRedirect | xlib::CWBackPixel,
+                                    attrs);
+        self.overlay = id;
+    }
+
+    // show a resize cursor while the pointer is over a border
+    fn update_hover(&mut self, event: &xlib::XMotionEvent) {
+        let border = match self.workspaces.get_container(event.window) {
+            Some((_, c)) => { c.find_border(event.x, event.y) }
+            None => { return }
+        };
+        let shape = match border {
+            Some((id, _)) => {
+                match self.workspaces.get_container(id) {
+                    Some((_, c)) => {
+                        match c.direction {
+                            layout::LayoutDirection::Horizontal => SB_H_DOUBLE_ARROW,
+                            layout::LayoutDirection::Vertical => SB_V_DOUBLE_ARROW,
+                        }
+                    }
+                    None => { LEFT_PTR }
+                }
+            }
+            None => { LEFT_PTR }
+        };
+
+        if self.hover == Some((event.window, shape)) {
+            return
+        }
+        self.set_cursor(event.window, shape);
+        self.hover = if shape == LEFT_PTR { None } else { Some((event.window, shape)) };
+    }
+
+    fn set_cursor(&mut self, window: Window, shape: u32) {
+        let context = self.context;
+        let cursor = *self.cursors.entry(shape).or_insert_with(|| {
+            libx::create_font_cursor(context, shape)
+        });
+        libx::set_cursor(self.context, window, cursor);
+    }
+
+    pub fn handle_leave(&mut self, event: &xlib::XCrossingEvent) {
+        if let Some((window, _)) = self.hover {
+            if window == event.window && self.resizing.is_none() {
+                self.set_cursor(window, LEFT_PTR);
+                self.hover = None;
+            }
+        }
+    }
+
+    // move the dragged border to the pointer position in root coordinates
+    fn resize_border(&mut self, id: Window, x_root: i32, y_root: i32) {
+        if let Some((_, c)) = self.workspaces.get_container(id) {
+            if let container::Mode::Resize(index, x, y) = c.mode {
+                let rec = c.rec();
+                let (delta, extent) = match c.direction {
+                    layout::LayoutDirection::Vertical => { (y_root - y, rec.height) }
+                    layout::LayoutDirection::Horizontal => { (x_root - x, rec.width) }
+                };
+                if delta == 0 || extent == 0 {
+                    return
+                }
+
+                let step = c.resize_children(index-1, index, delta as f32 / extent as f32);
+                // only move the anchor as far as the border actually moved
+                let moved = (step * extent as f32) as i32;
+                c.mode = match c.direction {
+                    layout::LayoutDirection::Vertical => {
+                        container::Mode::Resize(index, x, y + moved)
+                    }
+                    layout::LayoutDirection::Horizontal => {
+                        container::Mode::Resize(index, x + moved, y)
+                    }
+                };
+                c.update_layout();
+            }
+        }
     }
 
     pub fn handle_button_press(&mut self, event: &xlib::XButtonEvent) {
+        if self.handle_mouse_binding(event) {
+            return
+        }
 
-        let id = match self.workspaces.get_container(event.window) {
+        let (id, on_titlebar) = match self.workspaces.get_container(event.window) {
             Some((_,c)) => {
                 let client = c.query_point(event.x, event.y);
                 match client {
                     Some(c) => {
-                        c.raw_id()
+                        (c.raw_id(), true)
                     }
-                    None => { c.raw_id() }
+                    None => { (c.raw_id(), false) }
                 }
             }
             None => { return }
         };
         self.workspaces.set_focus(id);
 
-        // test if press on boarder
-        if let Some((_, c)) = self.workspaces.get_container(event.window) {
-            let res = c.query_border(event.x, event.y);
-            if let Some(i) =res {
-                match c.mode {
-                    container::Mode::Normal => {
-                        c.mode = container::Mode::Resize(i, event.x, event.y)
-                    }
-                    container::Mode::Resize(index, x, y) => {
+        // a press on a titlebar may start dragging the window
+        if on_titlebar {
+            self.drag = Some(Drag {
+                window: id,
+                x: event.x_root,
+                y: event.y_root,
+                active: false,
+                target: None,
+            });
+            self.last_motion = event.time;
+        }
 
-                    }
-                    _ => {}
+        // test if press on border, of this container or an ancestor
+        let border = match self.workspaces.get_container(event.window) {
+            Some((_, c)) => { c.find_border(event.x, event.y) }
+            None => { None }
+        };
+        if let Some((owner, i)) = border {
+            if let Some((_, c)) = self.workspaces.get_container(owner) {
+                if let container::Mode::Normal = c.mode {
+                    c.mode = container::Mode::Resize(i, event.x_root, event.y_root);
+                    self.resizing = Some(owner);
+                    self.last_motion = event.time;
+                    self.drag = None;
                 }
             }
         }
     }
 
-    pub fn handle_button_release(&mut self, event: &xlib::XButtonEvent) {
-        if let Some((_, c)) = self.workspaces.get_container(event.window) {
-            match c.mode {
-                container::Mode::Resize(index, x, y) => {
-                    let dx = event.x - x;
-                    let dy = event.y - y;
-                    let rec = c.rec();
-                    let step = match c.direction {
-                        layout::LayoutDirection::Vertical => {
-                            dy as f32 / rec.height as f32
+    // run the bindmouse command for the click, false if none is bound
+    fn handle_mouse_binding(&mut self, event: &xlib::XButtonEvent) -> bool {
+        let mask = event.state & MODIFIER_MASK & !self.lock_mask;
+
+        let (target, focus) = if event.window == self.context.root {
+            (handler::MouseTarget::Root, None)
+        }
+        else {
+            match self.workspaces.get_container(event.window) {
+                Some((_, c)) => {
+                    match c.category {
+                        container::Type::App => {
+                            (handler::MouseTarget::Window, Some(c.raw_id()))
                         }
-                        layout::LayoutDirection::Horizontal => {
-                            dx as f32 / rec.width as f32
+                        _ => {
+                            if let Some(client) = c.query_point(event.x, event.y) {
+                                (handler::MouseTarget::Titlebar, Some(client.raw_id()))
+                            }
+                            else if c.find_border(event.x, event.y).is_some() {
+                                (handler::MouseTarget::Border, None)
+                            }
+                            else {
+                                return false
+                            }
                         }
-                    };
+                    }
+                }
+                None => { return false }
+            }
+        };
+
+        let bind = handler::MouseBind {
+            button: event.button,
+            mask: mask,
+            target: target,
+        };
+        let found = self.config.mousebinds.contains_key(&bind);
 
-                    c.resize_children(index-1, index, step);
-                    c.update_layout();
-                    c.mode = container::Mode::Normal;
+        // clicks on windows are grabbed synchronously, bound ones are
+        // ours and the others are passed on to the client
+        if target == handler::MouseTarget::Window {
+            let mode = if found { xlib::AsyncPointer } else { xlib::ReplayPointer };
+            libx::allow_events(self.context, mode);
+        }
+        if !found {
+            return false
+        }
+
+        if let Some(id) = focus {
+            self.workspaces.set_focus(id);
+        }
+        if let Some(handler) = self.config.mousebinds.get_mut(&bind) {
+            handler(&mut self.workspaces);
+        }
+        true
+    }
+
+    // grab the buttons of bindmouse lines for target on window
+    fn grab_buttons(&self, window: Window, target: handler::MouseTarget) {
+        let sync = target == handler::MouseTarget::Window;
+        for bind in self.config.mousebinds.keys() {
+            if bind.target != target {
+                continue
+            }
+            // plain clicks on the root can't be grabbed without stealing every click
+            if target == handler::MouseTarget::Root && bind.mask == 0 {
+                continue
+            }
+            for lock in self.locks.iter() {
+                libx::grab_button(self.context, bind.button, bind.mask | lock, window, sync);
+            }
+        }
+    }
+
+    pub fn handle_button_release(&mut self, event: &xlib::XButtonEvent) {
+        if let Some(id) = self.resizing {
+            self.resize_border(id, event.x_root, event.y_root);
+            if let Some((_, c)) = self.workspaces.get_container(id) {
+                c.mode = container::Mode::Normal;
+            }
+            self.resizing = None;
+        }
+
+        if let Some(drag) = self.drag.take() {
+            if drag.active {
+                libx::unmap_window(self.context, self.overlay);
+                if let Some((target, zone)) = drag.target {
+                    self.workspaces.drop_window(drag.window, target, zone);
                 }
-                _ => {}
             }
         }
     }
 
+    pub fn handle_key_press(&mut self, event: &xlib::XKeyEvent) {
+        self.handle_key(event, false);
+    }
+
     pub fn handle_key_release(&mut self, event: &xlib::XKeyEvent) {
-        if event.state > 0 {
-            let sym = libx::lookup_keysym(*event, 0);
-            let b = handler::KeyBind {
-                key: sym,
-                mask: event.state,
-            };
-            debug!("key {} {}", event.state, sym);
+        self.handle_key(event, true);
+    }
 
-            match self.config.bindsyms.get_mut(&b) {
-                Some(handler) => {
-                    handler(&mut self.workspaces);
+    fn handle_key(&mut self, event: &xlib::XKeyEvent, release: bool) {
+        let sym = libx::lookup_keysym(*event, 0);
+        if libx::is_modifier_key(sym) {
+            // letting go of the last held modifier ends a focus mru cycle
+            if release && self.workspaces.is_cycling() {
+                let held = event.state & MODIFIER_MASK & !self.lock_mask & !libx::modifier_mask(self.context, sym);
+                if held == 0 {
+                    self.workspaces.finish_cycle();
+                    self.update_keyboard_grab();
                 }
-                None => {
-                    println!("no bind");
+            }
+            return
+        }
+
+        let mode = self.workspaces.bind_mode.clone();
+        // button bits are set while a mouse button is held
+        let mask = event.state & MODIFIER_MASK & !self.lock_mask;
+        debug!("key {} {} {}", event.state, sym, release);
+
+        if !self.chord.is_empty() && mask == 0 && sym == keysym::XK_Escape as xlib::KeySym {
+            if !release {
+                self.cancel_chord();
+            }
+            return
+        }
+        // keycode bindings take precedence over keysym bindings
+        let by_code = handler::KeyBind {
+            key: 0,
+            code: event.keycode as xlib::KeyCode,
+            mask: mask,
+            release: release,
+        };
+        let by_sym = handler::KeyBind {
+            key: sym,
+            code: 0,
+            mask: mask,
+            release: release,
+        };
+        let mut keys = Vec::new();
+        for b in vec![by_code, by_sym] {
+            let mut k = self.chord.clone();
+            k.push(b);
+            if self.config.get_binding(&mode, &k).is_some() {
+                keys = k;
+                break
+            }
+        }
+
+        if keys.is_empty() {
+            // releases of keys bound on press are expected
+            if !release {
+                debug!("no binding");
+                if !self.chord.is_empty() {
+                    self.cancel_chord();
                 }
             }
+            return
+        }
+
+        let mut prefix = false;
+        match self.config.get_binding(&mode, &keys) {
+            Some(&mut handler::Binding::Handler(ref mut handler)) => {
+                handler(&mut self.workspaces);
+            }
+            Some(&mut handler::Binding::Prefix(_)) => {
+                prefix = true;
+            }
+            None => {}
+        }
+
+        if prefix {
+            let timeout = Duration::from_millis(self.config.chord_timeout);
+            self.chord = keys;
+            self.chord_deadline = Some(Instant::now() + timeout);
+        }
+        else {
+            self.chord.clear();
+            self.chord_deadline = None;
+        }
+        self.update_chord_hint();
+        self.update_keyboard_grab();
+    }
+
+    pub fn handle_mapping(&mut self, event: &xlib::XMappingEvent) {
+        libx::refresh_keyboard_mapping(event);
+        if event.request == xlib::MappingKeyboard || event.request == xlib::MappingModifier {
+            libx::ungrab_keys(self.context, self.context.root);
+            self.grab_keys();
+        }
+    }
+
+    fn cancel_chord(&mut self) {
+        self.chord.clear();
+        self.chord_deadline = None;
+        self.update_chord_hint();
+        self.update_keyboard_grab();
+    }
+
+    fn update_chord_hint(&mut self) {
+        let hint = if self.chord.is_empty() {
+            None
+        }
+        else {
+            let keys: Vec<String> = self.chord.iter().map(|k| k.to_string()).collect();
+            Some(keys.join(" "))
+        };
+        for o in self.workspaces.outputs.iter_mut() {
+            o.bar.set_hint(hint.clone());
+            o.bar.update();
         }
     }
 
+    // the keyboard is grabbed while a non-default mode, a chord or a
+    // focus mru cycle is active
+    fn update_keyboard_grab(&mut self) {
+        let grab = self.workspaces.bind_mode != config::DEFAULT_MODE || !self.chord.is_empty() || self.workspaces.is_cycling();
+        if grab == self.keyboard_grabbed {
+            return
+        }
+        if grab {
+            libx::grab_keyboard(self.context, self.context.root);
+        }
+        else {
+            libx::ungrab_keyboard(self.context);
+        }
+        self.keyboard_grabbed = grab;
+    }
+
     pub fn handle_property(&mut self, event: &xlib::XPropertyEvent) {
         let usertime = libx::get_atom(self.context, "_NET_WM_USER_TIME");
-        match event.atom {
-            usertime => {
-                self.workspaces.set_focus(event.window);
+        let net_wm_name = libx::get_atom(self.context, "_NET_WM_NAME");
+        if event.atom == usertime {
+            self.workspaces.set_focus(event.window);
+        }
+        else if event.atom == xlib::XA_WM_NAME || event.atom == net_wm_name {
+            match self.workspaces.get_container(event.window) {
+                Some((_, c)) => {
+                    let focused = c.is_focused();
+                    c.decorate(focused);
+                }
+                None => { return }
             }
+            // rules on the title may match now
+            self.apply_rules(event.window);
         }
     }
 
@@ -346,6 +1006,15 @@ impl WindowManager {
 
     pub fn handle(&mut self, e: xlib::XEvent) {
         let t = e.get_type();
+        if let Some(base) = self.randr_event {
+            if t == base + xrandr::RRScreenChangeNotify {
+                debug!("screen change");
+                let mut e = e;
+                libx::randr_update_configuration(&mut e);
+                self.update_outputs();
+                return
+            }
+        }
         match t {
             xlib::CreateNotify => {
                 let event: xlib::XCreateWindowEvent = From::from(e);
@@ -381,11 +1050,21 @@ impl WindowManager {
                 let event: xlib::XClientMessageEvent = From::from(e);
                 self.handle_client_message(&event);
             }
+            xlib::KeyPress => {
+                debug!("key press");
+                let mut event: xlib::XKeyEvent = From::from(e);
+                self.handle_key_press(&mut event);
+            }
             xlib::KeyRelease => {
                 debug!("key release");
                 let mut event: xlib::XKeyEvent = From::from(e);
                 self.handle_key_release(&mut event);
             }
+            xlib::MappingNotify => {
+                let event: xlib::XMappingEvent = From::from(e);
+                debug!("mapping notify {}", event.request);
+                self.handle_mapping(&event);
+            }
             xlib::MotionNotify => {
                 let mut event: xlib::XMotionEvent = From::from(e);
                 debug!("motion {} {} {}", event.window, event.x, event.y);
@@ -424,7 +1103,7 @@ impl WindowManager {
             xlib::LeaveNotify => {
                 let mut event: xlib::XCrossingEvent = From::from(e);
                 debug!("leave window {} {} {} {} {}", event.window, event.x, event.y, event.x_root, event.y_root);
-                // self.handle_enter(&event);
+                self.handle_leave(&event);
             }
             xlib::PropertyNotify => {
                 let mut event: xlib::XPropertyEvent = From::from(e);
@@ -439,50 +1118,233 @@ impl WindowManager {
     pub fn run(&mut self) {
         loop {
             //handle events here
-            let mut e = libx::next_event(self.context);
-            if let Some(b) = self.workspaces.taskbar.as_mut() {
-                b.handle(&e);
+            let deadline = self.workspaces.launches.iter()
+                .map(|l| l.deadline())
+                .chain(self.chord_deadline)
+                .min();
+            let timeout = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if deadline > now { Some(deadline - now) } else { Some(Duration::from_millis(0)) }
+                }
+                None => { None }
+            };
+
+            if let Some(e) = libx::next_event_timeout(self.context, timeout, self.wake) {
+                for o in self.workspaces.outputs.iter_mut() {
+                    o.bar.handle(&e);
+                }
+                let dismissed = match self.nagbar.as_mut() {
+                    Some(b) => { b.handle(&e) }
+                    None => { false }
+                };
+                if dismissed {
+                    self.nagbar = None;
+                }
+                self.handle(e);
+            }
+            // empty the pipe, the atomics say which signals came
+            let mut buf = [0u8; 64];
+            while unsafe { libc::read(self.wake, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) } > 0 {}
+
+            if CHILD_EXITED.swap(false, Ordering::SeqCst) {
+                // a program that exited won't map a window anymore,
+                // unless it handed its startup id on
+                for pid in reap_children() {
+                    startup::child_exited(&mut self.workspaces.launches, pid);
+                }
+            }
+            let now = Instant::now();
+            self.workspaces.launches.retain(|l| l.deadline() > now);
+            self.update_busy_cursor();
+
+            if RELOAD.swap(false, Ordering::SeqCst) || self.workspaces.reload {
+                self.workspaces.reload = false;
+                self.reload();
+            }
+
+            if let Some(deadline) = self.chord_deadline {
+                if Instant::now() >= deadline {
+                    self.cancel_chord();
+                }
             }
-            self.handle(e);
         }
     }
 
     pub fn init(&mut self) {
         // init connection setting
         let mask = 0x1A0034;
-        let mask = xlib::SubstructureRedirectMask | xlib::SubstructureNotifyMask | xlib::ButtonPressMask;
+        // startup notification messages are sent with PropertyChangeMask
+        let mask = xlib::SubstructureRedirectMask | xlib::SubstructureNotifyMask | xlib::ButtonPressMask | xlib::PropertyChangeMask;
 
+        self.wake = wake_pipe();
         unsafe{
             xlib::XSetErrorHandler(Some(error_handler));
+            libc::signal(libc::SIGHUP, sighup_handler as libc::sighandler_t);
+            libc::signal(libc::SIGCHLD, sigchld_handler as libc::sighandler_t);
         }
-        let left_ptr: u32 = 68;
-        libx::define_cursor(self.context, self.context.root, left_ptr);
+        // children left by whatever started us
+        reap_children();
+        let root = self.context.root;
+        self.set_cursor(root, LEFT_PTR);
 
         libx::select_input(self.context, self.context.root,
                            mask);
 
+        // load config file, the first workspaces go to their outputs
+        let errors = self.config.load(self.config_path.as_ref().map(|p| p.as_path()));
+        self.workspaces.focus_wrapping = self.config.focus_wrapping;
+        self.workspaces.output_assigns = self.config.output_assigns.clone();
+        self.workspaces.modes = self.config.modes.keys().cloned().collect();
+
         self.init_workspaces();
+        self.create_overlay();
+        self.show_errors(&errors);
+
+        self.grab_keys();
+        let root = self.context.root;
+        self.grab_buttons(root, handler::MouseTarget::Root);
+        libx::sync(self.context, 0);
 
-        // load config file, run exec in config
-        self.config.load();
+        // everything is set up, start the programs of exec lines
+        self.run_execs(false);
+    }
+
+    // run exec_always lines, and exec lines too unless reloading
+    fn run_execs(&mut self, reload: bool) {
+        for exec in self.config.execs.iter() {
+            if reload && !exec.always {
+                continue
+            }
+            let args: Vec<&str> = exec.args.iter().map(|a| a.as_str()).collect();
+            handler::spawn(&mut self.workspaces, &mut config::build_cmd(&args), args[0], exec.workspace.clone());
+        }
+    }
+
+    /// read the config again and switch to it, unless it has errors
+    pub fn reload(&mut self) {
+        let mut config = Config::new();
+        let errors = config.load(self.config_path.as_ref().map(|p| p.as_path()));
+        self.show_errors(&errors);
+        if !errors.is_empty() {
+            warn!("config has errors, keeping the old one");
+            return
+        }
+
+        self.cancel_chord();
+        let root = self.context.root;
+        let windows = self.workspaces.windows();
+        libx::ungrab_keys(self.context, root);
+        libx::ungrab_buttons(self.context, root);
+        for w in windows.iter() {
+            libx::ungrab_buttons(self.context, *w);
+        }
+
+        let old_height = self.config.titlebar_height;
+        self.config = config;
+        self.workspaces.focus_wrapping = self.config.focus_wrapping;
+        self.workspaces.output_assigns = self.config.output_assigns.clone();
+        self.workspaces.modes = self.config.modes.keys().cloned().collect();
+        self.workspaces.assign_outputs();
+        let mode = self.workspaces.bind_mode.clone();
+        if mode != config::DEFAULT_MODE && !self.config.modes.contains_key(&mode) {
+            self.workspaces.set_bind_mode(config::DEFAULT_MODE);
+            self.update_keyboard_grab();
+        }
+
+        self.grab_keys();
+        self.grab_buttons(root, handler::MouseTarget::Root);
+        for w in windows.iter() {
+            self.grab_buttons(*w, handler::MouseTarget::Window);
+        }
+        let height = self.config.titlebar_height;
+        self.workspaces.set_titlebar_height(old_height, height);
+
+        // rules only run on windows mapped from now on
+        let all: Vec<usize> = (0..self.config.rules.len()).collect();
+        self.applied = windows.iter().map(|w| (*w, all.clone())).collect();
+
+        self.run_execs(true);
+    }
+
+    // log config errors and show the first ones in a nagbar
+    fn show_errors(&mut self, errors: &[config::ConfigError]) {
+        if errors.is_empty() {
+            self.nagbar = None;
+            return
+        }
+        let mut lines = vec![format!("{} error(s) in the config, click to dismiss", errors.len())];
+        for (i, e) in errors.iter().enumerate() {
+            error!("{}", e);
+            if i < 5 {
+                lines.push(e.to_string());
+            }
+        }
+        if errors.len() > 5 {
+            lines.push("...".to_string());
+        }
+        let bar = NagBar::new(self.context, lines);
+        bar.update();
+        self.nagbar = Some(bar);
+    }
+
+    // grab every binding once for each combination of lock modifiers,
+    // so bindings keep working with NumLock or CapsLock on
+    fn grab_keys(&mut self) {
+        let numlock = libx::modifier_mask(self.context, keysym::XK_Num_Lock as xlib::KeySym);
+        let scrolllock = libx::modifier_mask(self.context, keysym::XK_Scroll_Lock as xlib::KeySym);
+        self.lock_mask = xlib::LockMask | numlock | scrolllock;
+
+        let mut locks: Vec<u32> = vec![0];
+        for &lock in [xlib::LockMask, numlock, scrolllock].iter() {
+            if lock == 0 {
+                continue
+            }
+            let combined: Vec<u32> = locks.iter().map(|m| m | lock).collect();
+            locks.extend(combined);
+        }
+        locks.sort();
+        locks.dedup();
+        self.locks = locks;
 
         for bind in self.config.bindsyms.keys() {
-            let code = libx::keysym_to_keycode(self.context, bind.key);
-            libx::grab_key(self.context, code, bind.mask, self.context.root);
+            let code = if bind.code != 0 {
+                bind.code
+            }
+            else {
+                libx::keysym_to_keycode(self.context, bind.key)
+            };
+            for lock in self.locks.iter() {
+                libx::grab_key(self.context, code, bind.mask | lock, self.context.root);
+            }
         }
-        libx::sync(self.context, 0);
     }
 
     fn init_workspaces(&mut self) {
+        self.update_outputs();
+        let root = self.workspaces.current().raw_id();
+        self.workspaces.set_focus(root);
+    }
+
+    // lay the workspaces out on the RandR outputs, or on the whole root
+    // window without RandR
+    fn update_outputs(&mut self) {
         let attrs = libx::get_window_attributes(self.context, self.context.root);
-        let taskbar_height: u32 = 16;
-        self.workspaces.rec = Some(layout::Rectangle {
-            x: attrs.x,
-            y: attrs.y + taskbar_height as i32,
-            width: attrs.width as u32,
-            height: attrs.height as u32 - taskbar_height,
-        });
-        self.workspaces.taskbar = Some(TaskBar::new(self.context, 20, 1));
-        self.workspaces.switch_workspace('1');
+        let mut outputs = match self.randr_event {
+            Some(_) => { libx::get_outputs(self.context) }
+            None => { Vec::new() }
+        };
+        if outputs.is_empty() {
+            outputs.push(("default".to_string(), attrs.x, attrs.y, attrs.width as u32, attrs.height as u32));
+        }
+        let outputs = outputs.into_iter().map(|(name, x, y, width, height)| {
+            (name, layout::Rectangle {
+                x: x,
+                y: y,
+                width: width,
+                height: height,
+            })
+        }).collect();
+        self.workspaces.set_outputs(outputs);
     }
 }
@@ -2,71 +2,482 @@ extern crate x11;
 extern crate libc;
 
 use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::mem;
 use x11::xlib::{Window};
 use super::container::{ self, Container };
-use super::layout;
+use super::layout::{ self, DropZone, MoveDirection };
 use super::TaskBar;
+use super::config::DEFAULT_MODE;
+use super::startup::Launch;
 use super::super::libx::{ self, Context };
 
+// height of the taskbar at the top of every output
+const TASKBAR_HEIGHT: u32 = 20;
+
+/// a monitor, showing one of the workspaces that live on it
+pub struct Output {
+    pub name: String,
+    // area the workspaces are laid out in, below the taskbar
+    pub rec: layout::Rectangle,
+    pub current: String,
+    pub bar: TaskBar,
+}
+
 pub struct Workspaces {
-    current: char,
+    // workspace of the focused output
+    current: String,
     pub context: Context,
     pub mode: container::Mode,
-    pub rec: Option<layout::Rectangle>,
-    pub taskbar: Option<TaskBar>,
-    pub spaces: HashMap<char, Container>,
+    pub bind_mode: String,
+    // directional focus wraps around at the edge of the workspace
+    pub focus_wrapping: bool,
+    // container selected with focus parent, commands act on it
+    pub selected: Option<Window>,
+    // window commands act on instead of the focus, while rules run
+    pub target: Option<Window>,
+    // windows of all workspaces, most recently focused first
+    pub history: Vec<Window>,
+    // set by the reload command, the window manager reloads the config
+    pub reload: bool,
+    // programs whose first window has not been mapped yet
+    pub launches: Vec<Launch>,
+    // outputs workspaces should live on, the first one that exists
+    pub output_assigns: Vec<(String, Vec<String>)>,
+    // names of the binding modes the config defines
+    pub modes: Vec<String>,
+    // snapshot of history and position while cycling with focus mru
+    cycle: Option<(Vec<Window>, usize)>,
+    pub outputs: Vec<Output>,
+    // index of the output with focus
+    output: usize,
+    // name of the output each workspace lives on
+    homes: HashMap<String, String>,
+    pub spaces: HashMap<String, Container>,
 }
 
 impl Workspaces {
     pub fn new(context: Context) -> Workspaces {
         Workspaces {
-            current: '0',
+            current: String::new(),
             mode: container::Mode::Normal,
+            bind_mode: DEFAULT_MODE.to_string(),
+            focus_wrapping: true,
+            selected: None,
+            target: None,
+            history: Vec::new(),
+            cycle: None,
+            reload: false,
+            launches: Vec::new(),
+            output_assigns: Vec::new(),
+            modes: Vec::new(),
             context: context,
             spaces: HashMap::new(),
-            taskbar: None,
-            rec: None,
+            outputs: Vec::new(),
+            output: 0,
+            homes: HashMap::new(),
         }
     }
 
-    pub fn contain(&self, key: char) -> bool {
-        self.spaces.contains_key(&key)
+    pub fn contain(&self, key: &str) -> bool {
+        self.spaces.contains_key(key)
     }
 
-    pub fn create(&mut self, key: char) {
+    /// create a workspace on its assigned output or the focused one
+    pub fn create(&mut self, key: &str) {
+        let output = self.assigned_output(key).unwrap_or(self.output);
+        self.create_on(key, output);
+    }
+
+    fn create_on(&mut self, key: &str, output: usize) {
         let mut space = Container::new(self.context);
-        if self.rec.is_some() {
-            let r = self.rec.unwrap();
+        if let Some(o) = self.outputs.get(output) {
+            let r = o.rec;
             space.configure(r.x, r.y, r.width, r.height);
+            self.homes.insert(key.to_string(), o.name.clone());
         }
         space.category = container::Type::Workspace;
-        self.spaces.insert(key, space);
+        self.spaces.insert(key.to_string(), space);
+        // the map may have moved the other workspaces
+        for (_, w) in self.spaces.iter_mut() {
+            w.relink();
+        }
 
-        // update taskbar
-        if let Some(bar) = self.taskbar.as_mut() {
-            let keys = self.spaces.keys().map(|c| c.clone()).collect::<Vec<char>>();
-            bar.load(keys);
-            bar.update();
+        self.update_taskbars();
+    }
+
+    pub fn delete(&mut self, key: &str) {
+        self.spaces.remove(key);
+        self.homes.remove(key);
+    }
+
+    /// index of the output workspace key lives on
+    pub fn output_of(&self, key: &str) -> Option<usize> {
+        match self.homes.get(key) {
+            Some(name) => { self.output_named(name) }
+            None => { None }
+        }
+    }
+
+    /// the whole area of the output workspace key lives on, taskbar
+    /// included, or of the screen without outputs
+    pub fn output_area(&self, key: &str) -> layout::Rectangle {
+        match self.output_of(key) {
+            Some(i) => {
+                let r = self.outputs[i].rec;
+                layout::Rectangle {
+                    x: r.x,
+                    y: r.y - TASKBAR_HEIGHT as i32,
+                    width: r.width,
+                    height: r.height + TASKBAR_HEIGHT,
+                }
+            }
+            None => {
+                layout::Rectangle {
+                    x: 0,
+                    y: 0,
+                    width: libx::display_width(self.context, self.context.screen_num),
+                    height: libx::display_height(self.context, self.context.screen_num),
+                }
+            }
+        }
+    }
+
+    pub fn output_named(&self, name: &str) -> Option<usize> {
+        self.outputs.iter().position(|o| o.name == name)
+    }
+
+    // the first existing output workspace key is assigned to
+    fn assigned_output(&self, key: &str) -> Option<usize> {
+        assigned(&self.output_assigns, key, &self.output_names())
+    }
+
+    fn output_names(&self) -> Vec<&str> {
+        self.outputs.iter().map(|o| o.name.as_str()).collect()
+    }
+
+    // name for a new workspace on output: the first one assigned there
+    // that doesn't exist, else the lowest number not in use or assigned
+    // to another output
+    fn free_key(&self, output: usize) -> String {
+        let key = self.output_assigns.iter()
+            .map(|&(ref k, _)| k)
+            .find(|k| !self.contain(k) && self.assigned_output(k) == Some(output));
+        match key {
+            Some(k) => { k.clone() }
+            None => {
+                (1..).map(|n: u32| n.to_string())
+                    .find(|k| !self.contain(k) && self.assigned_output(k).map_or(true, |o| o == output))
+                    .unwrap()
+            }
+        }
+    }
+
+    // workspaces living on output, sorted
+    fn keys_on(&self, output: usize) -> Vec<String> {
+        let name = &self.outputs[output].name;
+        let mut keys: Vec<String> = self.homes.iter()
+            .filter(|&(_, h)| h == name)
+            .map(|(k, _)| k.clone())
+            .collect();
+        keys.sort_by(|a, b| order(a, b));
+        keys
+    }
+
+    /// names of all workspaces in taskbar order
+    pub fn names(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.spaces.keys().cloned().collect();
+        keys.sort_by(|a, b| order(a, b));
+        keys
+    }
+
+    /// give workspace old the name new, unless new is taken
+    pub fn rename(&mut self, old: &str, new: &str) -> bool {
+        if self.contain(new) || !self.contain(old) {
+            return false
+        }
+        let space = self.spaces.remove(old).unwrap();
+        self.spaces.insert(new.to_string(), space);
+        // the map may have moved the workspaces
+        for (_, w) in self.spaces.iter_mut() {
+            w.relink();
+        }
+        if let Some(home) = self.homes.remove(old) {
+            self.homes.insert(new.to_string(), home);
+        }
+        for o in self.outputs.iter_mut() {
+            if o.current == old {
+                o.current = new.to_string();
+            }
+        }
+        if self.current == old {
+            self.current = new.to_string();
         }
+        for l in self.launches.iter_mut() {
+            if l.workspace == old {
+                l.workspace = new.to_string();
+            }
+        }
+        for &mut (ref mut k, _) in self.output_assigns.iter_mut() {
+            if k == old {
+                *k = new.to_string();
+            }
+        }
+        self.update_taskbars();
+        true
     }
 
-    pub fn delete(&mut self, key: char) {
-        self.spaces.remove(&key);
+    /// the nearest output in direction from the focused one, or the
+    /// farthest on the other side if focus_wrapping is set
+    pub fn output_towards(&self, direction: MoveDirection) -> Option<usize> {
+        self.output_beside(self.output, direction, self.focus_wrapping)
     }
 
-    pub fn get(&mut self, key: char) -> Option<&mut Container>{
-        self.spaces.get_mut(&key)
+    // the nearest output in direction from output from, or the farthest
+    // on the other side if wrap is set
+    fn output_beside(&self, from: usize, direction: MoveDirection, wrap: bool) -> Option<usize> {
+        let center = |r: layout::Rectangle| (r.x + r.width as i32 / 2, r.y + r.height as i32 / 2);
+        let (cx, cy) = match self.outputs.get(from) {
+            Some(o) => { center(o.rec) }
+            None => { return None }
+        };
+        // distance ahead in direction, and off to the side
+        let distance = |i: &usize| {
+            let (x, y) = center(self.outputs[*i].rec);
+            match direction {
+                MoveDirection::Left => { (cx - x, (cy - y).abs()) }
+                MoveDirection::Right => { (x - cx, (cy - y).abs()) }
+                MoveDirection::Up => { (cy - y, (cx - x).abs()) }
+                MoveDirection::Down => { (y - cy, (cx - x).abs()) }
+            }
+        };
+        let others: Vec<usize> = (0..self.outputs.len()).filter(|&i| i != from).collect();
+        let ahead = others.iter().filter(|i| distance(i).0 > 0).min_by_key(|i| distance(i));
+        match ahead {
+            Some(&i) => { Some(i) }
+            None if wrap => { others.iter().min_by_key(|i| distance(i)).cloned() }
+            None => { None }
+        }
+    }
+
+    /// lay the workspaces out on new outputs, given with their whole
+    /// geometry. Outputs keep their workspaces, those of removed outputs
+    /// move to the first one. New outputs take the hidden workspaces
+    /// assigned to them and show one of them, or a new workspace.
+    pub fn set_outputs(&mut self, outputs: Vec<(String, layout::Rectangle)>) {
+        if outputs.is_empty() {
+            return
+        }
+        let focused = self.outputs.get(self.output).map(|o| o.name.clone());
+        let mut old = mem::replace(&mut self.outputs, Vec::new());
+        let mut added = Vec::new();
+        for (name, rec) in outputs.into_iter() {
+            let area = layout::Rectangle {
+                x: rec.x,
+                y: rec.y + TASKBAR_HEIGHT as i32,
+                width: rec.width,
+                height: rec.height - TASKBAR_HEIGHT,
+            };
+            match old.iter().position(|o| o.name == name) {
+                Some(i) => {
+                    let mut o = old.remove(i);
+                    o.rec = area;
+                    o.bar.configure(rec.x, rec.y, rec.width);
+                    self.outputs.push(o);
+                }
+                None => {
+                    added.push(self.outputs.len());
+                    self.outputs.push(Output {
+                        name: name,
+                        rec: area,
+                        current: String::new(),
+                        bar: TaskBar::new(self.context, rec.x, rec.y, rec.width, TASKBAR_HEIGHT),
+                    });
+                }
+            }
+        }
+
+        // workspaces of removed outputs go to the first output, hidden
+        let first = self.outputs[0].name.clone();
+        for o in old.iter() {
+            for (key, home) in self.homes.iter_mut() {
+                if *home == o.name {
+                    *home = first.clone();
+                    if let Some(w) = self.spaces.get(key) {
+                        w.unmap();
+                    }
+                }
+            }
+        }
+
+        for &i in added.iter() {
+            let visible: Vec<String> = self.outputs.iter().map(|o| o.current.clone()).collect();
+            for k in self.names() {
+                if !visible.contains(&k) && self.assigned_output(&k) == Some(i) {
+                    let name = self.outputs[i].name.clone();
+                    self.homes.insert(k, name);
+                }
+            }
+            let key = match self.keys_on(i).into_iter().next() {
+                Some(k) => { k }
+                None => { self.free_key(i) }
+            };
+            self.outputs[i].current = key.clone();
+            if !self.contain(&key) {
+                self.create_on(&key, i);
+            }
+            if let Some(w) = self.get(&key) {
+                w.map();
+            }
+        }
+
+        let outputs = &self.outputs;
+        let homes = &self.homes;
+        for (key, w) in self.spaces.iter_mut() {
+            let rec = match homes.get(key).and_then(|h| outputs.iter().find(|o| o.name == *h)) {
+                Some(o) => { o.rec }
+                None => { continue }
+            };
+            w.configure(rec.x, rec.y, rec.width, rec.height);
+        }
+
+        self.output = match focused {
+            Some(name) => { self.output_named(&name).unwrap_or(0) }
+            None => { 0 }
+        };
+        self.current = self.outputs[self.output].current.clone();
+        self.update_taskbars();
+    }
+
+    /// move the current workspace to output and show it there. The
+    /// output it leaves shows another of its workspaces or a new one.
+    pub fn move_workspace_to_output(&mut self, output: usize) {
+        let from = self.output;
+        if output == from || output >= self.outputs.len() {
+            return
+        }
+        let key = self.current.clone();
+        let other = match self.keys_on(from).into_iter().find(|k| *k != key) {
+            Some(k) => { k }
+            None => { self.free_key(from) }
+        };
+        if !self.contain(&other) {
+            self.create_on(&other, from);
+        }
+        self.outputs[from].current = other.clone();
+        if let Some(w) = self.get(&other) {
+            w.map();
+            w.update_layout();
+        }
+
+        let hidden = self.outputs[output].current.clone();
+        if let Some(w) = self.get(&hidden) {
+            w.unmap();
+        }
+        let name = self.outputs[output].name.clone();
+        let rec = self.outputs[output].rec;
+        self.homes.insert(key.clone(), name);
+        self.outputs[output].current = key.clone();
+        self.output = output;
+        if let Some(w) = self.get(&key) {
+            w.configure(rec.x, rec.y, rec.width, rec.height);
+            w.focus();
+        }
+        self.update_taskbars();
+
+        if let Some(w) = self.current().last_focused() {
+            self.set_focus(w);
+        }
+    }
+
+    /// move the workspaces to the outputs they are assigned to, after the
+    /// assignments changed. A visible workspace is shown on its new
+    /// output, the one it left shows another workspace.
+    pub fn assign_outputs(&mut self) {
+        let moves = misplaced(&self.output_assigns, &self.homes, &self.output_names());
+        if moves.is_empty() {
+            return
+        }
+        let focused = self.current.clone();
+        for (key, output) in moves {
+            let from = self.output_of(&key);
+            let name = self.outputs[output].name.clone();
+            let rec = self.outputs[output].rec;
+            self.homes.insert(key.clone(), name);
+            if let Some(w) = self.get(&key) {
+                w.configure(rec.x, rec.y, rec.width, rec.height);
+            }
+            let from = match from {
+                Some(f) if self.outputs[f].current == key => { f }
+                _ => { continue }
+            };
+
+            let other = match self.keys_on(from).into_iter().next() {
+                Some(k) => { k }
+                None => { self.free_key(from) }
+            };
+            if !self.contain(&other) {
+                self.create_on(&other, from);
+            }
+            self.outputs[from].current = other.clone();
+            if let Some(w) = self.get(&other) {
+                w.map();
+                w.update_layout();
+            }
+
+            let hidden = self.outputs[output].current.clone();
+            if let Some(w) = self.get(&hidden) {
+                w.unmap();
+            }
+            self.outputs[output].current = key.clone();
+            if let Some(w) = self.get(&key) {
+                w.update_layout();
+            }
+            if key == focused {
+                self.output = output;
+            }
+        }
+        self.current = self.outputs[self.output].current.clone();
+        self.update_taskbars();
+
+        if let Some(w) = self.current().last_focused() {
+            self.set_focus(w);
+        }
+    }
+
+    /// focus an output, and the workspace it shows
+    pub fn focus_output(&mut self, output: usize) {
+        let key = match self.outputs.get(output) {
+            Some(o) => { o.current.clone() }
+            None => { return }
+        };
+        self.switch_workspace(&key);
+    }
+
+    // show each output the workspaces living on it
+    fn update_taskbars(&mut self) {
+        for i in 0..self.outputs.len() {
+            let keys = self.keys_on(i);
+            let o = &mut self.outputs[i];
+            o.bar.load(keys);
+            o.bar.set_current(o.current.clone());
+            o.bar.update();
+        }
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<&mut Container>{
+        self.spaces.get_mut(key)
     }
 
     pub fn current(&mut self) -> &mut Container {
         self.spaces.get_mut(&self.current).unwrap()
     }
 
-    pub fn current_name(&self) -> char {
-        self.current
+    pub fn current_name(&self) -> String {
+        self.current.clone()
     }
 
-    pub fn switch_workspace(&mut self, new: char){
+    pub fn switch_workspace(&mut self, new: &str){
         if new == self.current {
             return
         }
@@ -76,26 +487,58 @@ impl Workspaces {
             self.create(new);
         }
 
-        let old = self.current;
-        if let Some(v) = self.get(old) {
-            v.unmap();
-            v.update_layout();
+        // the workspace shows on its own output, which gets focus
+        let output = self.output_of(new).unwrap_or(self.output);
+        let old = match self.outputs.get(output) {
+            Some(o) => { o.current.clone() }
+            None => { self.current.clone() }
+        };
+        if old != new {
+            if let Some(v) = self.get(&old) {
+                v.unmap();
+                v.update_layout();
+            }
+        }
+        if let Some(o) = self.outputs.get_mut(output) {
+            o.current = new.to_string();
         }
+        self.output = output;
 
-        self.current = new;
+        self.current = new.to_string();
         if let Some(v) = self.get(new) {
             println!("workspace {}", v.raw_id());
-            v.map();
+            if old != new {
+                v.map();
+            }
             v.focus();
             v.update_layout();
         }
 
-        // update taskbar
-        if let Some(bar) = self.taskbar.as_mut() {
-            bar.set_current(self.current);
-            bar.update();
+        self.update_taskbars();
+
+        if let Some(w) = self.current().last_focused() {
+            self.set_focus(w);
         }
+    }
 
+    pub fn set_bind_mode(&mut self, name: &str) {
+        // a mode without bindings would take the keyboard for good
+        if name != DEFAULT_MODE && !self.modes.iter().any(|m| m == name) {
+            warn!("mode {}: no such mode", name);
+            return
+        }
+        self.bind_mode = name.to_string();
+
+        // update taskbar
+        for o in self.outputs.iter_mut() {
+            if name == DEFAULT_MODE {
+                o.bar.set_mode(None);
+            }
+            else {
+                o.bar.set_mode(Some(name.to_string()));
+            }
+            o.bar.update();
+        }
     }
 
     pub fn can_manage(context: libx::Context, id: Window) -> bool {
@@ -104,7 +547,7 @@ impl Workspaces {
         attrs.override_redirect == 0 && transientfor_hint == 0
     }
 
-    pub fn move_window(&mut self, window: Window, from: char, to: char){
+    pub fn move_window(&mut self, window: Window, from: &str, to: &str){
         if from == to {
             return
         }
@@ -115,20 +558,51 @@ impl Workspaces {
         if !self.contain(to) {
             self.create(to);
         }
+        if self.selected == Some(window) {
+            self.select(None);
+        }
 
-        let res = match self.get(from) {
-            Some(w) => { w.remove(window) }
+        let (pid, floating) = match self.get(from) {
+            Some(w) => {
+                match w.tree_search(window) {
+                    Some(c) => { (c.get_parent().map(|p| p.raw_id()), c.is_floating()) }
+                    None => { (None, false) }
+                }
+            }
+            None => { (None, false) }
+        };
+        let res = match pid {
+            Some(pid) => {
+                match self.get_container(pid) {
+                    Some((_, p)) => { p.remove(window) }
+                    None => { None }
+                }
+            }
             None => { None }
         };
+        if let Some(pid) = pid {
+            self.prune(pid);
+        }
 
+        if let Some(w) = self.get(from) {
+            w.update_layout();
+        }
         if let Some(w) = self.get(to) {
             if res.is_some(){
-                w.add(res.unwrap());
+                // floating windows keep floating where they are
+                if floating {
+                    let rec = res.as_ref().unwrap().rec();
+                    w.add_floating(res.unwrap(), rec.x, rec.y);
+                }
+                else {
+                    w.add(res.unwrap());
+                }
+                w.update_layout();
             }
         }
     }
 
-    pub fn add_window(&mut self, container: Container, workspace: Option<char>) {
+    pub fn add_window(&mut self, container: Container, workspace: Option<&str>) {
         let w = match workspace {
             Some(k) => {
                 match self.get(k) {
@@ -153,17 +627,271 @@ impl Workspaces {
     pub fn insert_window(&mut self, container: Container) {
         if let Some(c) = self.get_focus() {
             if let Some(p) = c.get_parent(){
-                let index = p.contain(c.raw_id()).unwrap();
-                p.insert(index+1, container);
-                p.update_layout();
-                p.print_tree(0);
-                return
+                // next to a floating window is anywhere on its workspace
+                if let Some(index) = p.contain(c.raw_id()) {
+                    p.insert(index+1, container);
+                    p.update_layout();
+                    p.print_tree(0);
+                    return
+                }
             }
         }
         self.add_window(container, None);
     }
 
+    /// take window out of the tree and leave it where it is, floating
+    /// above the tiled windows of its workspace
+    pub fn float_window(&mut self, window: Window) {
+        if self.selected == Some(window) {
+            self.select(None);
+        }
+        let (key, rec, pid) = match self.get_container(window) {
+            Some((k, c)) => {
+                if !c.is_app() || c.is_floating() {
+                    return
+                }
+                (k, c.root_rec(), c.get_parent().map(|p| p.raw_id()))
+            }
+            None => { return }
+        };
+        let c = match pid.and_then(|pid| self.get_container(pid)) {
+            Some((_, p)) => { p.remove(window) }
+            None => { None }
+        };
+        if let Some(pid) = pid {
+            self.prune(pid);
+        }
+        if let (Some(c), Some(w)) = (c, self.get(&key)) {
+            let origin = w.root_rec();
+            w.add_floating(c, rec.x - origin.x, rec.y - origin.y);
+            w.update_layout();
+        }
+        self.set_focus(window);
+    }
+
+    /// put a floating window back into the tree of its workspace
+    pub fn tile_window(&mut self, window: Window) {
+        let key = match self.get_container(window) {
+            Some((k, c)) => {
+                if !c.is_floating() {
+                    return
+                }
+                k
+            }
+            None => { return }
+        };
+        if let Some(w) = self.get(&key) {
+            if let Some(c) = w.remove(window) {
+                w.add(c);
+            }
+            w.update_layout();
+        }
+        self.set_focus(window);
+    }
+
+    /// focus the window next to window in direction. Past the edge of
+    /// its workspace focus goes to the closest window of the workspace
+    /// shown on the adjacent output, and wraps around if there is none.
+    pub fn focus_towards(&mut self, window: Window, direction: MoveDirection) {
+        let wrap = self.focus_wrapping;
+        let (key, next, x, y) = match self.get_container(window) {
+            Some((k, c)) => {
+                let rec = c.root_rec();
+                let next = c.neighbor_leaf(direction.clone(), false);
+                (k, next, rec.x + rec.width as i32 / 2, rec.y + rec.height as i32 / 2)
+            }
+            None => { return }
+        };
+        if let Some(id) = next {
+            self.set_focus(id);
+            return
+        }
+
+        let from = self.output_of(&key).unwrap_or(self.output);
+        if let Some(i) = self.output_beside(from, direction.clone(), false) {
+            let other = self.outputs[i].current.clone();
+            let leaf = match self.get(&other) {
+                Some(w) => { w.closest_leaf(x, y) }
+                None => { return }
+            };
+            self.set_focus(leaf);
+            return
+        }
+
+        if !wrap {
+            return
+        }
+        let next = match self.get_container(window) {
+            Some((_, c)) => { c.neighbor_leaf(direction, true) }
+            None => { None }
+        };
+        if let Some(id) = next {
+            self.set_focus(id);
+        }
+    }
+
+    /// the window under a point in root coordinates on the current
+    /// workspace, with its geometry in root coordinates
+    pub fn query_window(&mut self, x: i32, y: i32) -> Option<(Window, layout::Rectangle)> {
+        let context = self.context;
+        let id = {
+            let w = self.current();
+            let rec = w.rec();
+            match w.query_window(x - rec.x, y - rec.y) {
+                Some(id) => { id }
+                None => { return None }
+            }
+        };
+        match self.get_container(id) {
+            Some((_, c)) => {
+                let rec = c.rec();
+                let (rx, ry) = libx::root_position(context, id);
+                Some((id, layout::Rectangle {
+                    x: rx,
+                    y: ry,
+                    width: rec.width,
+                    height: rec.height,
+                }))
+            }
+            None => { None }
+        }
+    }
+
+    /// move window next to target as zone says
+    pub fn drop_window(&mut self, window: Window, target: Window, zone: DropZone) {
+        if window == target {
+            return
+        }
+        let mut dragged = self.remove_window(window);
+        if let Some((_, t)) = self.get_container(target) {
+            if let Some(c) = dragged.take() {
+                dragged = t.drop_client(c, zone);
+            }
+        }
+        // put it back somewhere if the target went away
+        if let Some(c) = dragged {
+            self.add_window(c, None);
+        }
+        self.current().update_layout();
+        self.set_focus(window);
+    }
+
+    /// swap window with its sibling in direction, move it into the
+    /// neighbouring container, or out of its parent at the edge
+    pub fn move_direction(&mut self, window: Window, direction: MoveDirection) {
+        let axis = direction.axis();
+        let forward = direction.forward();
+
+        let (pid, index, neighbor) = {
+            let c = match self.get_container(window) {
+                Some((_, c)) => { c }
+                None => { return }
+            };
+            let p = match c.get_parent() {
+                Some(p) => { p }
+                None => { return }
+            };
+            let index = match p.contain(window) {
+                Some(index) => { index }
+                None => { return }
+            };
+            let neighbor = if p.orientation() != axis {
+                None
+            }
+            else if forward && index + 1 < p.size() {
+                Some(index + 1)
+            }
+            else if !forward && index > 0 {
+                Some(index - 1)
+            }
+            else {
+                None
+            };
+            (p.raw_id(), index, neighbor)
+        };
+
+        if let Some(n) = neighbor {
+            if let Some((_, p)) = self.get_container(pid) {
+                if p.clients[n].is_app() {
+                    p.clients.swap(index, n);
+                    p.relink();
+                }
+                else if let Some(c) = p.remove(window) {
+                    let n = if n > index { n - 1 } else { n };
+                    let target = p.get_child(n).unwrap();
+                    if forward {
+                        target.insert(0, c);
+                    }
+                    else {
+                        target.add(c);
+                    }
+                }
+                p.update_layout();
+            }
+            self.focus_or_select(window);
+            return
+        }
+
+        // at the edge, find the first ancestor laid out along the axis
+        let (aid, child) = {
+            let c = self.get_container(window).unwrap().1;
+            let mut child = c.get_parent().unwrap();
+            loop {
+                match child.get_parent() {
+                    Some(a) => {
+                        if a.orientation() == axis {
+                            break (a.raw_id(), child.raw_id())
+                        }
+                        child = a;
+                    }
+                    None => { return }
+                }
+            }
+        };
+
+        let c = match self.get_container(pid) {
+            Some((_, p)) => { p.remove(window) }
+            None => { None }
+        };
+        if let Some(c) = c {
+            if let Some((_, a)) = self.get_container(aid) {
+                let i = a.contain(child).unwrap();
+                a.insert(if forward { i + 1 } else { i }, c);
+            }
+        }
+        self.prune(pid);
+        self.current().update_layout();
+        self.focus_or_select(window);
+    }
+
+    /// destroy the container if it is empty, and its ancestors that become
+    /// empty, like tree_remove does
+    fn prune(&mut self, id: Window) {
+        let mut id = id;
+        loop {
+            let pid = match self.get_container(id) {
+                Some((_, c)) => {
+                    if !c.is_empty() || c.is_app() {
+                        return
+                    }
+                    match c.get_parent() {
+                        Some(p) => { p.raw_id() }
+                        None => { return }
+                    }
+                }
+                None => { return }
+            };
+            if let Some((_, p)) = self.get_container(pid) {
+                if let Some(c) = p.remove(id) {
+                    c.destroy();
+                }
+            }
+            id = pid;
+        }
+    }
+
     pub fn remove_window(&mut self, window: Window) -> Option<Container>{
+        self.history.retain(|&w| w != window);
         for (k, workspace) in self.spaces.iter_mut() {
             let res =  workspace.tree_remove(window);
             if let Some(w) = res {
@@ -176,12 +904,292 @@ impl Workspaces {
     }
 
     pub fn set_focus(&mut self, window: Window) {
+        if self.selected.is_some() {
+            self.select(None);
+        }
         if let Some(w) = self.get_focus() {
             w.unfocus();
         }
 
-        if let Some((_, c)) = self.get_container(window) {
-            c.focus()
+        let key = match self.get_container(window) {
+            Some((k, c)) => {
+                c.focus();
+                Some(k)
+            }
+            None => { None }
+        };
+        if let Some(k) = key {
+            if let Some(w) = self.get(&k) {
+                w.raise_floating(window);
+            }
+            self.follow_focus(&k);
+        }
+        self.record_focus(window);
+    }
+
+    // make the output showing workspace key the focused one
+    fn follow_focus(&mut self, key: &str) {
+        if key == self.current {
+            return
+        }
+        let output = match self.outputs.iter().position(|o| o.current == key) {
+            Some(i) => { i }
+            None => { return }
+        };
+        self.output = output;
+        self.current = key.to_string();
+        self.update_taskbars();
+    }
+
+    // move window to the front of the global history and of the
+    // focus stack of every ancestor
+    fn record_focus(&mut self, window: Window) {
+        if self.cycle.is_some() {
+            return
+        }
+        match self.get_container(window) {
+            Some((_, c)) => {
+                if !c.is_app() {
+                    return
+                }
+            }
+            None => { return }
+        }
+        self.history.retain(|&w| w != window);
+        self.history.insert(0, window);
+
+        let mut id = window;
+        loop {
+            let pid = match self.get_container(id) {
+                Some((_, c)) => {
+                    match c.get_parent() {
+                        Some(p) => {
+                            p.push_focus(id);
+                            p.raw_id()
+                        }
+                        None => { return }
+                    }
+                }
+                None => { return }
+            };
+            id = pid;
+        }
+    }
+
+    /// give windows that have the old default titlebar height the new
+    /// one, keeping those changed by border commands
+    pub fn set_titlebar_height(&mut self, old: u32, new: u32) {
+        for (_, w) in self.spaces.iter_mut() {
+            w.visit(&mut |c: &mut Container| {
+                if c.is_app() && c.titlebar_height == old {
+                    c.titlebar_height = new;
+                }
+            });
+            w.update_layout();
+        }
+    }
+
+    /// the App windows of all workspaces
+    pub fn windows(&self) -> Vec<Window> {
+        let mut windows = Vec::new();
+        for (_, w) in self.spaces.iter() {
+            windows.extend(w.leaves());
+        }
+        windows
+    }
+
+    /// ids of the containers above window, nearest first
+    pub fn ancestors(&mut self, window: Window) -> Vec<Window> {
+        let mut ancestors = Vec::new();
+        let mut id = window;
+        loop {
+            let pid = match self.get_container(id) {
+                Some((_, c)) => {
+                    match c.get_parent() {
+                        Some(p) => { p.raw_id() }
+                        None => { break }
+                    }
+                }
+                None => { break }
+            };
+            ancestors.push(pid);
+            id = pid;
+        }
+        ancestors
+    }
+
+    /// focus the window that had focus last under the nearest of
+    /// ancestors still on the current workspace, or the workspace
+    pub fn restore_focus(&mut self, ancestors: &[Window]) {
+        let current = self.current.clone();
+        for id in ancestors.iter() {
+            let leaf = match self.get_container(*id) {
+                Some((k, c)) => {
+                    if k != current {
+                        continue
+                    }
+                    c.last_focused()
+                }
+                None => { continue }
+            };
+            if let Some(w) = leaf {
+                self.set_focus(w);
+                return
+            }
+        }
+        let root = self.current().raw_id();
+        self.set_focus(root);
+    }
+
+    /// focus a window on any workspace, switching to it first
+    pub fn focus_anywhere(&mut self, window: Window) -> bool {
+        let key = match self.get_container(window) {
+            Some((k, _)) => { k }
+            None => { return false }
+        };
+        if key != self.current {
+            self.switch_workspace(&key);
+        }
+        self.set_focus(window);
+        true
+    }
+
+    /// go back to the window focused before the current one
+    pub fn focus_last(&mut self) {
+        let (focus, _) = libx::get_input_focus(self.context);
+        let last = self.history.iter().find(|&&w| w != focus).map(|w| *w);
+        if let Some(w) = last {
+            self.focus_anywhere(w);
+        }
+    }
+
+    /// step through the history without reordering it until
+    /// finish_cycle, like alt-tab
+    pub fn cycle_focus(&mut self, forward: bool) {
+        if self.cycle.is_none() {
+            if self.history.len() < 2 {
+                return
+            }
+            self.cycle = Some((self.history.clone(), 0));
+        }
+        let size = self.cycle.as_ref().unwrap().0.len();
+        for _ in 0..size {
+            let window = {
+                let cycle = self.cycle.as_mut().unwrap();
+                cycle.1 = if forward { (cycle.1 + 1) % size } else { (cycle.1 + size - 1) % size };
+                cycle.0[cycle.1]
+            };
+            // windows may be gone since the cycle started
+            if self.focus_anywhere(window) {
+                break
+            }
+        }
+    }
+
+    pub fn is_cycling(&self) -> bool {
+        self.cycle.is_some()
+    }
+
+    /// end the cycle, making the window it stopped at the most recent
+    pub fn finish_cycle(&mut self) {
+        if self.cycle.take().is_some() {
+            let (focus, _) = libx::get_input_focus(self.context);
+            self.record_focus(focus);
+        }
+    }
+
+    /// highlight a container so commands act on it as a unit,
+    /// None goes back to the focused window
+    pub fn select(&mut self, id: Option<Window>) {
+        if let Some(old) = self.selected.take() {
+            if let Some((_, c)) = self.get_container(old) {
+                c.selected = false;
+                c.decorate(false);
+            }
+        }
+        if let Some(new) = id {
+            if let Some((_, c)) = self.get_container(new) {
+                c.selected = true;
+                c.decorate(true);
+                self.selected = Some(new);
+            }
+        }
+    }
+
+    /// the target of a rule, the selected container, or the focused
+    /// window if none is selected
+    pub fn get_selected(&mut self) -> Option<&mut Container> {
+        if let Some(id) = self.target {
+            return self.get_container(id).map(|(_, c)| c)
+        }
+        if let Some(id) = self.selected {
+            if self.get_container(id).is_some() {
+                return self.get_container(id).map(|(_, c)| c)
+            }
+        }
+        self.get_focus()
+    }
+
+    pub fn focus_parent(&mut self) {
+        let pid = match self.get_selected() {
+            Some(c) => {
+                match c.get_parent() {
+                    Some(p) => {
+                        match p.category {
+                            container::Type::Workspace => { None }
+                            _ => { Some(p.raw_id()) }
+                        }
+                    }
+                    None => { None }
+                }
+            }
+            None => { None }
+        };
+        if pid.is_some() {
+            self.select(pid);
+        }
+    }
+
+    pub fn focus_child(&mut self) {
+        let id = match self.selected {
+            Some(id) => { id }
+            None => { return }
+        };
+        let (focus, _) = libx::get_input_focus(self.context);
+        let child = match self.get_container(id) {
+            Some((_, c)) => {
+                let child = match c.child_towards(focus) {
+                    Some(child) => { Some(child) }
+                    None => { c.clients.get(0) }
+                };
+                child.map(|child| (child.raw_id(), child.is_app()))
+            }
+            None => { None }
+        };
+        match child {
+            Some((id, true)) => {
+                self.set_focus(id);
+            }
+            Some((id, false)) => {
+                self.select(Some(id));
+            }
+            None => {
+                self.select(None);
+            }
+        }
+    }
+
+    // focus a window, or keep a container selected
+    fn focus_or_select(&mut self, id: Window) {
+        let is_app = match self.get_container(id) {
+            Some((_, c)) => { c.is_app() }
+            None => { return }
+        };
+        if is_app {
+            self.set_focus(id);
+        }
+        else {
+            self.select(Some(id));
         }
     }
 
@@ -196,11 +1204,11 @@ impl Workspaces {
         }
     }
 
-    pub fn get_container(&mut self, id: Window) -> Option<(char, &mut Container)>{
+    pub fn get_container(&mut self, id: Window) -> Option<(String, &mut Container)>{
         for (k, w) in self.spaces.iter_mut() {
             let r = w.tree_search(id);
             if r.is_some(){
-                return Some((*k, r.unwrap()))
+                return Some((k.clone(), r.unwrap()))
             }
         }
         None
@@ -219,3 +1227,121 @@ impl Workspaces {
     //     None
     // }
 }
+
+/// taskbar order of workspace names: those starting with a number
+/// first by that number, then the others alphabetically
+pub fn order(a: &str, b: &str) -> Ordering {
+    fn number(s: &str) -> Option<u64> {
+        let digits: String = s.chars().take_while(|c| c.is_digit(10)).collect();
+        digits.parse().ok()
+    }
+    match (number(a), number(b)) {
+        (Some(x), Some(y)) => { x.cmp(&y).then(a.cmp(b)) }
+        (Some(_), None) => { Ordering::Less }
+        (None, Some(_)) => { Ordering::Greater }
+        (None, None) => { a.cmp(b) }
+    }
+}
+
+// index in outputs of the first existing output workspace key is
+// assigned to
+fn assigned(assigns: &[(String, Vec<String>)], key: &str, outputs: &[&str]) -> Option<usize> {
+    match assigns.iter().find(|&&(ref k, _)| k == key) {
+        Some(&(_, ref names)) => {
+            names.iter().filter_map(|n| outputs.iter().position(|o| o == n)).next()
+        }
+        None => { None }
+    }
+}
+
+// workspaces living elsewhere than the output they are assigned to,
+// with the index of that output, sorted
+fn misplaced(assigns: &[(String, Vec<String>)], homes: &HashMap<String, String>, outputs: &[&str]) -> Vec<(String, usize)> {
+    let mut moves: Vec<(String, usize)> = homes.iter()
+        .filter_map(|(key, home)| {
+            match assigned(assigns, key, outputs) {
+                Some(i) if outputs[i] != home => { Some((key.clone(), i)) }
+                _ => { None }
+            }
+        })
+        .collect();
+    moves.sort_by(|a, b| order(&a.0, &b.0));
+    moves
+}
+
+// workspaces holding a tree of windows that exist only in memory, the
+// calls below must not reach the X server
+#[cfg(test)]
+fn test_workspaces(windows: &[Window]) -> Workspaces {
+    let context: Context = unsafe { mem::zeroed() };
+    let mut workspaces = Workspaces::new(context);
+    let mut space = Container::from_id(context, 1);
+    space.category = container::Type::Workspace;
+    for w in windows.iter() {
+        space.clients.push(Container::from_id(context, *w));
+    }
+    workspaces.spaces.insert("1".to_string(), space);
+    workspaces.current = "1".to_string();
+    workspaces.current().relink();
+    workspaces
+}
+
+#[test]
+fn test_selected_target() {
+    let mut workspaces = test_workspaces(&[2, 3]);
+    // commands behind criteria act on the matched window, not the focus
+    workspaces.target = Some(3);
+    assert_eq!(workspaces.get_selected().map(|c| c.raw_id()), Some(3));
+    workspaces.target = Some(4);
+    assert!(workspaces.get_selected().is_none());
+}
+
+#[test]
+fn test_output_assigns() {
+    let assigns = vec![
+        ("2".to_string(), vec!["HDMI-1".to_string(), "DP-1".to_string()]),
+        ("mail".to_string(), vec!["DP-1".to_string()]),
+        ("9".to_string(), vec!["VGA-1".to_string()]),
+    ];
+    // the first assigned output that exists wins
+    assert_eq!(assigned(&assigns, "2", &["eDP-1", "DP-1"]), Some(1));
+    assert_eq!(assigned(&assigns, "2", &["DP-1", "HDMI-1"]), Some(1));
+    assert_eq!(assigned(&assigns, "9", &["eDP-1", "DP-1"]), None);
+    assert_eq!(assigned(&assigns, "1", &["eDP-1", "DP-1"]), None);
+
+    let mut homes = HashMap::new();
+    for &(key, home) in [("1", "eDP-1"), ("2", "eDP-1"), ("mail", "eDP-1"), ("3", "DP-1"), ("9", "eDP-1")].iter() {
+        homes.insert(key.to_string(), home.to_string());
+    }
+    let moves = misplaced(&assigns, &homes, &["eDP-1", "DP-1"]);
+    assert_eq!(moves, vec![("2".to_string(), 1), ("mail".to_string(), 1)]);
+    homes.insert("2".to_string(), "DP-1".to_string());
+    let moves = misplaced(&assigns, &homes, &["eDP-1", "DP-1", "HDMI-1"]);
+    assert_eq!(moves, vec![("2".to_string(), 2), ("mail".to_string(), 1)]);
+}
+
+#[test]
+fn test_order() {
+    let mut names = vec!["mail", "10", "2: web", "1", "chat"];
+    names.sort_by(|a, b| order(a, b));
+    assert_eq!(names, vec!["1", "2: web", "10", "chat", "mail"]);
+}
+
+#[test]
+fn test_rename() {
+    let mut workspaces = test_workspaces(&[2]);
+    workspaces.launches.push(Launch::new("a".to_string(), None, "1".to_string(), true));
+    workspaces.launches.push(Launch::new("b".to_string(), None, "2".to_string(), false));
+    workspaces.output_assigns.push(("1".to_string(), vec!["DP-1".to_string()]));
+    workspaces.spaces.insert("2".to_string(), Container::from_id(workspaces.context, 5));
+
+    assert!(!workspaces.rename("1", "2"));
+    assert!(workspaces.rename("1", "mail"));
+    assert_eq!(workspaces.current_name(), "mail");
+    assert!(!workspaces.contain("1"));
+    // launches and output assignments follow the new name
+    assert_eq!(workspaces.launches[0].workspace, "mail");
+    assert_eq!(workspaces.launches[1].workspace, "2");
+    assert_eq!(workspaces.output_assigns[0].0, "mail");
+    assert!(workspaces.get_container(2).is_some());
+}
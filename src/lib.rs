@@ -2,6 +2,8 @@
 extern crate log;
 extern crate x11;
 extern crate libc;
+extern crate regex;
+extern crate glob;
 // #[macro_use]
 // extern crate glium;
 // extern crate glutin;
@@ -9,8 +9,11 @@ use std::mem;
 use std::str;
 use std::slice;
 use std::boxed::Box;
+use std::time::Duration;
 
 use x11::xlib;
+use x11::keysym;
+use x11::xrandr;
 use x11::xlib::{ Display, Window };
 use libc::{ c_int, c_long, c_uint, c_ulong, c_void };
 
@@ -33,6 +36,7 @@ pub struct Context {
     pub unfocus_gc: xlib::GC,
     pub focus_font_gc: xlib::GC,
     pub unfocus_font_gc: xlib::GC,
+    pub background_gc: xlib::GC,
     pub fontset: xlib::XFontSet,
 }
 
@@ -101,6 +105,38 @@ pub fn next_event(context: Context) -> xlib::XEvent {
     }
 }
 
+/// like next_event, but gives up after timeout if no event arrives,
+/// when a signal interrupts the wait or when wake becomes readable
+pub fn next_event_timeout(context: Context, timeout: Option<Duration>, wake: c_int) -> Option<xlib::XEvent> {
+    let ms = match timeout {
+        Some(t) => { (t.as_secs() * 1000 + (t.subsec_nanos() / 1000000) as u64) as c_int }
+        None => { -1 }
+    };
+
+    unsafe {
+        // XPending flushes the output buffer before we wait
+        if xlib::XPending(context.display) == 0 {
+            let mut fds = [
+                libc::pollfd {
+                    fd: xlib::XConnectionNumber(context.display),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: wake,
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+            ];
+            libc::poll(fds.as_mut_ptr(), 2, ms);
+            if xlib::XPending(context.display) == 0 {
+                return None
+            }
+        }
+    }
+    Some(next_event(context))
+}
+
 pub fn define_cursor(context: Context, window: Window, shape: c_uint) {
     unsafe {
         let cursor = xlib::XCreateFontCursor(context.display, shape);
@@ -108,28 +144,79 @@ pub fn define_cursor(context: Context, window: Window, shape: c_uint) {
     }
 }
 
+pub fn create_font_cursor(context: Context, shape: c_uint) -> xlib::Cursor {
+    unsafe {
+        xlib::XCreateFontCursor(context.display, shape)
+    }
+}
+
+pub fn set_cursor(context: Context, window: Window, cursor: xlib::Cursor) {
+    unsafe {
+        xlib::XDefineCursor(context.display, window, cursor);
+    }
+}
+
 pub fn grab_key(context: Context, keycode: xlib::KeyCode, modifiers: c_uint, window: Window) {
     unsafe {
         xlib::XGrabKey(context.display, keycode as c_int, modifiers, window, 1, xlib::GrabModeAsync, xlib::GrabModeAsync);
     }
 }
 
-pub fn grab_button(context: Context, button: c_uint, modifiers: c_uint, window: Window) {
+pub fn ungrab_keys(context: Context, window: Window) {
+    unsafe {
+        xlib::XUngrabKey(context.display, xlib::AnyKey, xlib::AnyModifier, window);
+    }
+}
+
+pub fn refresh_keyboard_mapping(event: &xlib::XMappingEvent) {
+    let mut e = *event;
+    unsafe {
+        xlib::XRefreshKeyboardMapping(&mut e);
+    }
+}
+
+pub fn grab_keyboard(context: Context, window: Window) -> c_int {
+    unsafe {
+        xlib::XGrabKeyboard(context.display, window, 1,
+                            xlib::GrabModeAsync, xlib::GrabModeAsync,
+                            xlib::CurrentTime)
+    }
+}
+
+pub fn ungrab_keyboard(context: Context) {
+    unsafe {
+        xlib::XUngrabKeyboard(context.display, xlib::CurrentTime);
+    }
+}
+
+/// with sync the pointer is frozen until allow_events is called
+pub fn grab_button(context: Context, button: c_uint, modifiers: c_uint, window: Window, sync: bool) {
+    let pointer_mode = if sync { xlib::GrabModeSync } else { xlib::GrabModeAsync };
     unsafe {
         xlib::XGrabButton(context.display, button, modifiers, window,
                           0,
                           xlib::ButtonPressMask as c_uint,
-                          xlib::GrabModeAsync, xlib::GrabModeAsync,
+                          pointer_mode, xlib::GrabModeAsync,
                           0, 0);
     }
 }
 
+pub fn allow_events(context: Context, mode: c_int) {
+    unsafe {
+        xlib::XAllowEvents(context.display, mode, xlib::CurrentTime);
+    }
+}
+
 pub fn ungrab_button(context: Context, button: c_uint, modifiers: c_uint, window: Window) {
     unsafe{
         xlib::XUngrabButton(context.display, button, modifiers, window);
     }
 }
 
+pub fn ungrab_buttons(context: Context, window: Window) {
+    ungrab_button(context, xlib::AnyButton as c_uint, xlib::AnyModifier, window);
+}
+
 pub fn get_atom_name(context: Context, atom: xlib::Atom) -> Option<String> {
     unsafe{
         let name = xlib::XGetAtomName(context.display, atom);
@@ -189,6 +276,85 @@ pub fn get_text_property(context: Context, window: xlib::Window, atom: xlib::Ato
     }
 }
 
+/// instance and class from WM_CLASS
+pub fn get_class_hint(context: Context, window: Window) -> Option<(String, String)> {
+    unsafe{
+        let mut hint: xlib::XClassHint = mem::zeroed();
+        if xlib::XGetClassHint(context.display, window, &mut hint) == 0 {
+            return None
+        }
+        let mut res = None;
+        if hint.res_name != ptr::null_mut() && hint.res_class != ptr::null_mut() {
+            let name = ffi::CStr::from_ptr(hint.res_name).to_string_lossy().into_owned();
+            let class = ffi::CStr::from_ptr(hint.res_class).to_string_lossy().into_owned();
+            res = Some((name, class));
+        }
+        if hint.res_name != ptr::null_mut() {
+            xlib::XFree(hint.res_name as *mut c_void);
+        }
+        if hint.res_class != ptr::null_mut() {
+            xlib::XFree(hint.res_class as *mut c_void);
+        }
+        res
+    }
+}
+
+/// the atoms of a property of type ATOM, like _NET_WM_WINDOW_TYPE
+pub fn get_atom_property(context: Context, window: Window, property: xlib::Atom) -> Vec<xlib::Atom> {
+    unsafe{
+        let mut actual_type: xlib::Atom = 0;
+        let mut format: c_int = 0;
+        let mut count: c_ulong = 0;
+        let mut remaining: c_ulong = 0;
+        let mut data: *mut u8 = ptr::null_mut();
+        let r = xlib::XGetWindowProperty(context.display, window, property,
+                                         0, 32, xlib::False, xlib::XA_ATOM,
+                                         &mut actual_type, &mut format,
+                                         &mut count, &mut remaining, &mut data);
+        let mut vec = Vec::new();
+        if r == xlib::Success as c_int && data != ptr::null_mut() {
+            if format == 32 {
+                vec = slice::from_raw_parts(data as *const c_ulong, count as usize).iter()
+                    .map(|&a| a as xlib::Atom).collect();
+            }
+            xlib::XFree(data as *mut c_void);
+        }
+        vec
+    }
+}
+
+/// the first value of a CARDINAL property, like _NET_WM_PID
+pub fn get_cardinal_property(context: Context, window: Window, property: xlib::Atom) -> Option<u32> {
+    get_long_property(context, window, property, xlib::XA_CARDINAL).map(|v| v as u32)
+}
+
+/// the window in a WINDOW property, like WM_CLIENT_LEADER
+pub fn get_window_id_property(context: Context, window: Window, property: xlib::Atom) -> Option<Window> {
+    get_long_property(context, window, property, xlib::XA_WINDOW).map(|v| v as Window)
+}
+
+fn get_long_property(context: Context, window: Window, property: xlib::Atom, kind: xlib::Atom) -> Option<c_ulong> {
+    unsafe{
+        let mut actual_type: xlib::Atom = 0;
+        let mut format: c_int = 0;
+        let mut count: c_ulong = 0;
+        let mut remaining: c_ulong = 0;
+        let mut data: *mut u8 = ptr::null_mut();
+        let r = xlib::XGetWindowProperty(context.display, window, property,
+                                         0, 1, xlib::False, kind,
+                                         &mut actual_type, &mut format,
+                                         &mut count, &mut remaining, &mut data);
+        let mut value = None;
+        if r == xlib::Success as c_int && data != ptr::null_mut() {
+            if format == 32 && count > 0 {
+                value = Some(*(data as *const c_ulong));
+            }
+            xlib::XFree(data as *mut c_void);
+        }
+        value
+    }
+}
+
 pub fn get_wm_protocols(context: Context, window: Window) -> Vec<xlib::Atom>{
     unsafe{
         let mut atoms: *mut xlib::Atom = ptr::null_mut();
@@ -305,6 +471,18 @@ pub fn get_children(context: Context, window: Window) -> Option<Vec<Window>> {
     }
 }
 
+/// position of the window's origin in root coordinates
+pub fn root_position(context: Context, window: Window) -> (i32, i32) {
+    let mut x = 0;
+    let mut y = 0;
+    let mut child: Window = 0;
+    unsafe {
+        xlib::XTranslateCoordinates(context.display, window, context.root,
+                                    0, 0, &mut x, &mut y, &mut child);
+    }
+    (x, y)
+}
+
 pub fn reparent(context: Context, window: Window, parent: Window, x: c_int, y: c_int) {
     unsafe {
         xlib::XReparentWindow(context.display, window, parent, x, y);
@@ -398,6 +576,12 @@ pub fn resize_window(context: Context, window: Window, x: i32, y: i32, width: u3
     configure_window(context, window, mask, change);
 }
 
+pub fn destroy_window(context: Context, window: Window) {
+    unsafe{
+        xlib::XDestroyWindow(context.display, window);
+    }
+}
+
 pub fn map_window(context: Context, window: Window) -> c_int{
     unsafe{
         xlib::XMapWindow(context.display, window)
@@ -446,6 +630,9 @@ pub fn keysym_to_string(keysym: c_ulong) -> Option<String> {
     let status: *mut xlib::XComposeStatus = ptr::null_mut();
     unsafe{
         let s = xlib::XKeysymToString(keysym);
+        if s == ptr::null_mut() {
+            return None
+        }
         let cstr = ffi::CStr::from_ptr(s);
         match str::from_utf8(cstr.to_bytes()) {
             Ok(s) => {
@@ -458,6 +645,42 @@ pub fn keysym_to_string(keysym: c_ulong) -> Option<String> {
     }
 }
 
+/// true for Shift, Control, Super... keys themselves
+pub fn is_modifier_key(keysym: xlib::KeySym) -> bool {
+    let sym = keysym as c_uint;
+    (sym >= keysym::XK_Shift_L && sym <= keysym::XK_Hyper_R) ||
+        (sym >= keysym::XK_ISO_Lock && sym <= keysym::XK_ISO_Level5_Lock) ||
+        sym == keysym::XK_Mode_switch ||
+        sym == keysym::XK_Num_Lock
+}
+
+/// the modifier mask (Mod1Mask..Mod5Mask) the key of keysym is mapped to,
+/// 0 if it is not a modifier
+pub fn modifier_mask(context: Context, keysym: xlib::KeySym) -> c_uint {
+    let keycode = keysym_to_keycode(context, keysym);
+    if keycode == 0 {
+        return 0
+    }
+
+    let mut mask = 0;
+    unsafe {
+        let map = xlib::XGetModifierMapping(context.display);
+        if map == ptr::null_mut() {
+            return 0
+        }
+        let size = (*map).max_keypermod as usize;
+        let keys = slice::from_raw_parts((*map).modifiermap, 8 * size);
+        for i in 0..8 {
+            if keys[i*size..(i+1)*size].contains(&keycode) {
+                mask = 1 << i;
+                break
+            }
+        }
+        xlib::XFreeModifiermap(map);
+    }
+    mask
+}
+
 pub fn keysym_to_keycode(context: Context, keysym: xlib::KeySym) -> xlib::KeyCode {
     unsafe {
         xlib::XKeysymToKeycode(context.display, keysym)
@@ -553,6 +776,29 @@ pub fn draw_rectangle(context: Context, id: Window, x: i32, y: i32, width: u32,
     }
 }
 
+pub fn create_pixmap(context: Context, drawable: xlib::Drawable, width: u32, height: u32) -> xlib::Pixmap {
+    unsafe {
+        let depth = xlib::XDefaultDepth(context.display, context.screen_num);
+        xlib::XCreatePixmap(context.display, drawable, width, height, depth as c_uint)
+    }
+}
+
+pub fn free_pixmap(context: Context, pixmap: xlib::Pixmap) {
+    unsafe {
+        xlib::XFreePixmap(context.display, pixmap);
+    }
+}
+
+pub fn copy_area(context: Context, src: xlib::Drawable, dest: xlib::Drawable, x: i32, y: i32, width: u32, height: u32) {
+    unsafe {
+        xlib::XCopyArea(context.display,
+                        src, dest, context.gc,
+                        x, y,
+                        width, height,
+                        x, y);
+    }
+}
+
 pub fn fill_rectangle(context: Context, id: Window, x: i32, y: i32, width: u32, height: u32) {
     unsafe {
         xlib::XFillRectangle(context.display,
@@ -561,6 +807,78 @@ pub fn fill_rectangle(context: Context, id: Window, x: i32, y: i32, width: u32,
                              width, height);
     }
 }
+
+/// the first event number of RandR, None if the server lacks it
+pub fn randr_event_base(context: Context) -> Option<c_int> {
+    let mut event_base: c_int = 0;
+    let mut error_base: c_int = 0;
+    unsafe {
+        if xrandr::XRRQueryExtension(context.display, &mut event_base, &mut error_base) == 0 {
+            return None
+        }
+    }
+    Some(event_base)
+}
+
+/// get RRScreenChangeNotify events on the root when outputs change
+pub fn randr_select_input(context: Context) {
+    unsafe {
+        xrandr::XRRSelectInput(context.display, context.root, xrandr::RRScreenChangeNotifyMask);
+    }
+}
+
+/// let Xlib know the screen size changed
+pub fn randr_update_configuration(event: &mut xlib::XEvent) {
+    unsafe {
+        xrandr::XRRUpdateConfiguration(event);
+    }
+}
+
+/// name and geometry of every active output, the primary one first.
+/// Outputs showing the same area, like mirrored ones, are given once.
+pub fn get_outputs(context: Context) -> Vec<(String, c_int, c_int, c_uint, c_uint)> {
+    let mut outputs = Vec::new();
+    unsafe {
+        let res = xrandr::XRRGetScreenResourcesCurrent(context.display, context.root);
+        if res == ptr::null_mut() {
+            return outputs
+        }
+        let primary = xrandr::XRRGetOutputPrimary(context.display, context.root);
+        let ids = slice::from_raw_parts((*res).outputs, (*res).noutput as usize);
+        for &id in ids.iter() {
+            let info = xrandr::XRRGetOutputInfo(context.display, res, id);
+            if info == ptr::null_mut() {
+                continue
+            }
+            if (*info).crtc != 0 {
+                let crtc = xrandr::XRRGetCrtcInfo(context.display, res, (*info).crtc);
+                if crtc != ptr::null_mut() {
+                    if (*crtc).mode != 0 {
+                        let bytes = slice::from_raw_parts((*info).name as *const u8, (*info).nameLen as usize);
+                        let output = (String::from_utf8_lossy(bytes).into_owned(),
+                                      (*crtc).x, (*crtc).y, (*crtc).width, (*crtc).height);
+                        let mirror = outputs.iter().any(|o: &(String, c_int, c_int, c_uint, c_uint)| {
+                            (o.1, o.2, o.3, o.4) == (output.1, output.2, output.3, output.4)
+                        });
+                        if !mirror {
+                            if id == primary {
+                                outputs.insert(0, output);
+                            }
+                            else {
+                                outputs.push(output);
+                            }
+                        }
+                    }
+                    xrandr::XRRFreeCrtcInfo(crtc);
+                }
+            }
+            xrandr::XRRFreeOutputInfo(info);
+        }
+        xrandr::XRRFreeScreenResources(res);
+    }
+    outputs
+}
+
 #[cfg(test)]
 mod test{
 use super::*;
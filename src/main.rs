@@ -6,13 +6,55 @@ extern crate log;
 extern crate env_logger;
 
 extern crate rustile;
-use rustile::core::WindowManager;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
+use rustile::core::{WindowManager, Config};
 // use glium::{ DisplayBuild, Surface };
 
 fn main() {
     env_logger::init().unwrap();
 
+    let args: Vec<String> = env::args().collect();
+    let usage = "usage: rustile [-c <config>] [--check-config <config>]";
+    let mut config_path = None;
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-c" => {
+                match args.get(i+1) {
+                    Some(p) => { config_path = Some(PathBuf::from(p)); }
+                    None => {
+                        eprintln!("{}", usage);
+                        process::exit(2);
+                    }
+                }
+                i += 1;
+            }
+            "--check-config" => {
+                let path = match args.get(i+1) {
+                    Some(p) => { p }
+                    None => {
+                        eprintln!("{}", usage);
+                        process::exit(2);
+                    }
+                };
+                let errors = Config::check(Path::new(path));
+                for e in errors.iter() {
+                    eprintln!("{}", e);
+                }
+                process::exit(if errors.is_empty() { 0 } else { 1 });
+            }
+            _ => {
+                eprintln!("{}", usage);
+                process::exit(2);
+            }
+        }
+        i += 1;
+    }
+
     let mut wm = WindowManager::new();
+    wm.config_path = config_path;
     wm.init();
     wm.run();
 